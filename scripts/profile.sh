@@ -1,4 +1,4 @@
 #!/usr/bin/env bash
 
 cargo build --release
-sudo cargo flamegraph -- '2021-09-01T00:00:00.00Z' $(cat sp500.txt)
+sudo cargo flamegraph -- watch '2021-09-01T00:00:00.00Z' $(cat sp500.txt)
@@ -0,0 +1,119 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::messages::*;
+use actix::prelude::*;
+use tokio::sync::mpsc;
+
+/// Emits each split and dividend in the fetched histories once, the first time
+/// it shows up.
+pub struct ActionTracker {
+    action_tx: mpsc::Sender<CorporateAction>,
+    // dates of the splits and dividends seen so far, per symbol
+    seen: HashMap<String, (HashSet<u64>, HashSet<u64>)>,
+}
+
+impl ActionTracker {
+    pub fn new(bufsize: usize) -> (Self, mpsc::Receiver<CorporateAction>) {
+        let (action_tx, action_rx) = mpsc::channel(bufsize);
+        let tracker = Self {
+            action_tx,
+            seen: HashMap::new(),
+        };
+        (tracker, action_rx)
+    }
+
+    /// The actions in `history` that have not been observed before.
+    pub fn observe(&mut self, history: &StockHistory) -> Vec<CorporateAction> {
+        let (splits, dividends) = self.seen.entry(history.symbol.clone()).or_default();
+        let new_splits = history
+            .splits
+            .iter()
+            .filter(|s| splits.insert(s.date))
+            .map(|s| Action::Split(s.clone()));
+        let new_dividends = history
+            .dividends
+            .iter()
+            .filter(|d| dividends.insert(d.date))
+            .map(|d| Action::Dividend(d.clone()));
+
+        new_splits
+            .chain(new_dividends)
+            .map(|action| CorporateAction {
+                symbol: history.symbol.clone(),
+                action,
+                tick: history.tick,
+            })
+            .collect()
+    }
+}
+
+impl Actor for ActionTracker {
+    type Context = Context<Self>;
+}
+
+impl Handler<StockHistory> for ActionTracker {
+    type Result = ();
+
+    fn handle(&mut self, history: StockHistory, _: &mut Context<Self>) {
+        let actions = self.observe(&history);
+        if actions.is_empty() {
+            return;
+        }
+        let tx = self.action_tx.clone();
+        actix::spawn(async move {
+            for action in actions {
+                tx.send(action).await.unwrap();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+    use yahoo_finance_api::{Dividend, Split};
+
+    fn split(date: u64, numerator: u64, denominator: u64) -> Split {
+        Split {
+            date,
+            numerator,
+            denominator,
+            split_ratio: format!("{}:{}", numerator, denominator),
+        }
+    }
+
+    #[test]
+    fn tracker() {
+        let (mut tracker, _) = ActionTracker::new(1);
+        let mut history = StockHistory {
+            symbol: "AAPL".to_string(),
+            quotes: vec![],
+            missing: vec![],
+            instrument: None,
+            splits: vec![split(10, 4, 1)],
+            dividends: vec![Dividend {
+                amount: 0.2,
+                date: 20,
+            }],
+            from: Utc.timestamp(0, 0),
+            tick: 1,
+        };
+        assert_eq!(tracker.observe(&history).len(), 2);
+        assert!(tracker.observe(&history).is_empty());
+
+        history.dividends.push(Dividend {
+            amount: 0.22,
+            date: 30,
+        });
+        let actions = tracker.observe(&history);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(
+            actions[0].to_string(),
+            "AAPL dividend of 0.2200 on 1970-01-01"
+        );
+
+        history.symbol = "MSFT".to_string();
+        assert_eq!(tracker.observe(&history).len(), 3);
+    }
+}
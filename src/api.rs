@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+use actix::prelude::*;
+use actix_web::{dev::Server, error, web, App, HttpResponse, HttpServer};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::io;
+use yahoo_finance_api as yahoo;
+
+use crate::{messages::*, metrics::Metrics, resample::*, ws};
+
+/// Keeps the latest `StockInfo` and `StockHistory` for each symbol, so they
+/// can be queried by the HTTP API.
+#[derive(Default)]
+pub struct Cache {
+    infos: HashMap<String, StockInfo>,
+    histories: HashMap<String, StockHistory>,
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Actor for Cache {
+    type Context = Context<Self>;
+}
+
+impl Handler<StockInfo> for Cache {
+    type Result = ();
+
+    fn handle(&mut self, info: StockInfo, _: &mut Context<Self>) {
+        self.infos.insert(info.symbol.clone(), info);
+    }
+}
+
+impl Handler<StockHistory> for Cache {
+    type Result = ();
+
+    fn handle(&mut self, history: StockHistory, _: &mut Context<Self>) {
+        self.histories.insert(history.symbol.clone(), history);
+    }
+}
+
+impl Handler<GetSymbols> for Cache {
+    type Result = Vec<String>;
+
+    fn handle(&mut self, _: GetSymbols, _: &mut Context<Self>) -> Self::Result {
+        let mut symbols: Vec<String> = self.infos.keys().cloned().collect();
+        symbols.sort();
+        symbols
+    }
+}
+
+impl Handler<GetQuote> for Cache {
+    type Result = Option<StockInfo>;
+
+    fn handle(&mut self, GetQuote(symbol): GetQuote, _: &mut Context<Self>) -> Self::Result {
+        self.infos.get(&symbol).cloned()
+    }
+}
+
+impl Handler<GetHistory> for Cache {
+    type Result = Option<(Vec<yahoo::Quote>, Option<Instrument>)>;
+
+    fn handle(&mut self, query: GetHistory, _: &mut Context<Self>) -> Self::Result {
+        let from = query.from.map_or(i64::MIN, |dt| dt.timestamp());
+        let to = query.to.map_or(i64::MAX, |dt| dt.timestamp());
+        self.histories.get(&query.symbol).map(|history| {
+            let quotes = history
+                .quotes
+                .iter()
+                .filter(|q| (from..=to).contains(&(q.timestamp as i64)))
+                .cloned()
+                .collect();
+            (quotes, history.instrument.clone())
+        })
+    }
+}
+
+// `yahoo::Quote` does not implement `Serialize`
+#[derive(Serialize)]
+struct QuoteJson {
+    /// In the exchange's local time, if known.
+    timestamp: DateTime<FixedOffset>,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    adjclose: f64,
+    volume: u64,
+}
+
+impl QuoteJson {
+    fn new(q: yahoo::Quote, instrument: Option<&Instrument>) -> Self {
+        let timestamp = Utc.timestamp(q.timestamp as i64, 0);
+        Self {
+            timestamp: match instrument {
+                Some(instrument) => instrument.local_time(timestamp),
+                None => timestamp.into(),
+            },
+            open: q.open,
+            high: q.high,
+            low: q.low,
+            close: q.close,
+            adjclose: q.adjclose,
+            volume: q.volume,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Range {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    timeframe: Option<Timeframe>,
+}
+
+async fn symbols(cache: web::Data<Addr<Cache>>) -> actix_web::Result<HttpResponse> {
+    let symbols = cache
+        .send(GetSymbols)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(symbols))
+}
+
+async fn quote(
+    cache: web::Data<Addr<Cache>>,
+    symbol: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let info = cache
+        .send(GetQuote(symbol.into_inner()))
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(match info {
+        Some(info) => HttpResponse::Ok().json(info),
+        None => HttpResponse::NotFound().finish(),
+    })
+}
+
+async fn history(
+    cache: web::Data<Addr<Cache>>,
+    symbol: web::Path<String>,
+    range: web::Query<Range>,
+) -> actix_web::Result<HttpResponse> {
+    let Range {
+        from,
+        to,
+        timeframe,
+    } = range.into_inner();
+    let quotes = cache
+        .send(GetHistory {
+            symbol: symbol.into_inner(),
+            from,
+            to,
+        })
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(match quotes {
+        Some((mut quotes, instrument)) => {
+            if let Some(timeframe) = timeframe {
+                quotes = resample(&quotes, timeframe, instrument.as_ref());
+            }
+            HttpResponse::Ok().json(
+                quotes
+                    .into_iter()
+                    .map(|q| QuoteJson::new(q, instrument.as_ref()))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        None => HttpResponse::NotFound().finish(),
+    })
+}
+
+async fn metrics(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
+
+/// Binds the HTTP API to `addr`. The returned server needs to be spawned.
+///
+/// - `GET /symbols`: all symbols with a cached `StockInfo`
+/// - `GET /quote/{symbol}`: the latest `StockInfo` for `symbol`
+/// - `GET /history/{symbol}?from=&to=&timeframe=`: quotes for `symbol`,
+///   optionally restricted to an RFC 3339 range and resampled to a
+///   `Timeframe` like `1w`
+/// - `GET /ws?symbols=`: WebSocket pushing each new `StockInfo`, optionally
+///   restricted to a comma-separated list of symbols
+/// - `GET /metrics`: pipeline health in the Prometheus text format
+pub fn serve<A: std::net::ToSocketAddrs>(
+    addr: A,
+    cache: Addr<Cache>,
+    hub: Addr<ws::Hub>,
+    metrics: Metrics,
+) -> io::Result<Server> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(cache.clone()))
+            .app_data(web::Data::new(hub.clone()))
+            .app_data(web::Data::new(metrics.clone()))
+            .route("/symbols", web::get().to(symbols))
+            .route("/quote/{symbol}", web::get().to(quote))
+            .route("/history/{symbol}", web::get().to(history))
+            .route("/ws", web::get().to(ws::connect))
+            .route("/metrics", web::get().to(self::metrics))
+    })
+    .bind(addr)?
+    .run();
+    Ok(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+
+    fn history_of(symbol: &str, days: u32) -> StockHistory {
+        let quotes = (1..=days)
+            .map(|day| yahoo::Quote {
+                timestamp: Utc.ymd(2021, 10, day).and_hms(20, 0, 0).timestamp() as u64,
+                open: day as f64,
+                high: day as f64,
+                low: day as f64,
+                volume: 100,
+                close: day as f64,
+                adjclose: day as f64,
+            })
+            .collect();
+        StockHistory {
+            symbol: symbol.to_string(),
+            from: Utc.ymd(2021, 10, 1).and_hms(0, 0, 0),
+            quotes,
+            missing: vec![],
+            instrument: None,
+            splits: vec![],
+            dividends: vec![],
+            tick: 0,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn cache() {
+        let cache = Cache::new().start();
+        cache.send(history_of("MSFT", 5)).await.unwrap();
+        // a later history replaces the previous one
+        cache.send(history_of("MSFT", 7)).await.unwrap();
+
+        let history = |from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>| {
+            cache.send(GetHistory {
+                symbol: "MSFT".to_string(),
+                from,
+                to,
+            })
+        };
+        let closes = |quotes: Option<(Vec<yahoo::Quote>, _)>| {
+            quotes
+                .unwrap()
+                .0
+                .iter()
+                .map(|q| q.close)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(closes(history(None, None).await.unwrap()).len(), 7);
+        // both ends are inclusive
+        let from = Utc.ymd(2021, 10, 2).and_hms(20, 0, 0);
+        let to = Utc.ymd(2021, 10, 4).and_hms(20, 0, 0);
+        assert_eq!(
+            closes(history(Some(from), Some(to)).await.unwrap()),
+            vec![2.0, 3.0, 4.0]
+        );
+        assert_eq!(
+            closes(history(Some(to), None).await.unwrap()),
+            vec![4.0, 5.0, 6.0, 7.0]
+        );
+        assert!(cache
+            .send(GetHistory {
+                symbol: "AAPL".to_string(),
+                from: None,
+                to: None,
+            })
+            .await
+            .unwrap()
+            .is_none());
+        // symbols are those with a `StockInfo`, not just a history
+        assert!(cache.send(GetSymbols).await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn history_range() {
+        let cache = Cache::new().start();
+        cache.send(history_of("MSFT", 7)).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(cache))
+                .route("/history/{symbol}", web::get().to(history)),
+        )
+        .await;
+
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        let quotes: Vec<serde_json::Value> = test::call_and_read_body_json(
+            &app,
+            get("/history/MSFT?from=2021-10-03T00:00:00Z&to=2021-10-05T00:00:00Z"),
+        )
+        .await;
+        let closes: Vec<_> = quotes
+            .iter()
+            .map(|q| q["close"].as_f64().unwrap())
+            .collect();
+        assert_eq!(closes, vec![3.0, 4.0]);
+
+        let response = test::call_service(&app, get("/history/AAPL")).await;
+        assert_eq!(response.status(), 404);
+        let response = test::call_service(&app, get("/history/MSFT?from=yesterday")).await;
+        assert_eq!(response.status(), 400);
+    }
+}
@@ -41,7 +41,7 @@ mod bench {
 
     pub struct Benchmark {
         last_iter_start: std::time::Instant,
-        iters: Vec<Elapsed>,
+        iters: Vec<(Elapsed, super::ConnectionStats)>,
     }
 
     impl Benchmark {
@@ -53,16 +53,22 @@ mod bench {
                 iters,
             }
         }
-        pub fn iter_done(&mut self) {
-            self.iters.push(Elapsed::since(self.last_iter_start));
+        /// `stats` are those of this iteration only.
+        pub fn iter_done(&mut self, stats: super::ConnectionStats) {
+            self.iters
+                .push((Elapsed::since(self.last_iter_start), stats));
             self.last_iter_start = std::time::Instant::now();
         }
     }
 
     impl std::fmt::Display for Benchmark {
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            for e in self.iters.iter() {
-                write!(f, "{}\n", e)?;
+            for (e, stats) in self.iters.iter() {
+                writeln!(
+                    f,
+                    "{} ({} handshakes, {} DNS lookups)",
+                    e, stats.connections, stats.dns_lookups
+                )?;
             }
             Ok(())
         }
@@ -70,40 +76,63 @@ mod bench {
 }
 use bench::*;
 
+/// `rust-stock-tracker-bench [base url] [CA certificate]`, the arguments
+/// allow to run against a local stand-in instead of Yahoo.
 #[actix_rt::main]
 async fn main() {
+    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
+    init_logging(LogFormat::Human, &filter).unwrap();
+
     let from_date = Utc.ymd(2021, 9, 1).and_hms(0, 0, 0);
-    let symbols = std::fs::read_to_string("sp500.txt")
-        .unwrap()
-        .split(',')
-        .map(String::from)
-        .collect();
+    let symbols = read_symbols(std::path::Path::new("sp500.txt")).unwrap();
+    let n = symbols.len();
+
+    let mut args = std::env::args().skip(1);
+    let mut source = HttpSource::new().unwrap();
+    if let Some(base_url) = args.next() {
+        source = source.with_base_url(&base_url);
+    }
+    if let Some(path) = args.next() {
+        let pem = std::fs::read(&path).unwrap();
+        source = source.with_root_certificate(&pem).unwrap();
+    }
 
     let mut bench = Benchmark::start();
+    let metrics = Metrics::new().unwrap();
 
     let (fetcher, fetch_rx, mut fetch_err_rx) =
         Fetcher::new(symbols, from_date, tokio::time::Duration::from_millis(15));
-    let fetcher = fetcher.start();
+    let fetcher = fetcher
+        .with_timeout(Some(tokio::time::Duration::from_secs(10)))
+        .with_source(std::sync::Arc::new(source.clone()))
+        .with_metrics(metrics.clone())
+        .start();
 
-    let (transformer, mut info_rx) = Transformer::new(500).unwrap();
+    let (transformer, mut info_rx) = Transformer::new(n).unwrap();
     let transformer = transformer.start();
-    subscribe(transformer, fetch_rx);
+    subscribe_metered(transformer, fetch_rx, metrics.queue_depth("histories"));
 
     for _ in 0..5 {
+        let before = source.stats();
         let _ = fetcher.send(StartFetch).await;
-        // wait for all 500 messages
-        for _ in 0..500 {
+        // wait for a history or an error per symbol
+        for _ in 0..n {
             tokio::select! {
-                Some(err) = fetch_err_rx.recv() => eprintln!("{}", err),
+                Some(err) = fetch_err_rx.recv() => log_error(&err),
                 Some(info) = info_rx.recv() => println!("{}", info.fmt_csv()),
             }
         }
-        bench.iter_done();
+        // a fetch only counts as completed after its result was sent, and the
+        // next tick would skip a symbol that is still in flight
+        while fetcher.send(InFlight).await.unwrap_or(0) > 0 {
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        bench.iter_done(source.stats().since(&before));
     }
 
     println!("{}", bench);
 
-    // TODO: runs fine on good internet, not so fine on bad internet
-    // -> any way to find out number of messages stuck in
-    //	channels/mailboxes/buffers?
+    // runs fine on good internet, not so fine on bad internet, the queue
+    // depths tell how many messages got stuck in channels
+    eprintln!("{}", metrics.render());
 }
@@ -1,23 +1,73 @@
+use std::{path::PathBuf, sync::Arc};
+
 use actix::prelude::*;
 use chrono::prelude::*;
-use clap::clap_app;
-use tokio::time;
+use clap::{clap_app, AppSettings};
+use tokio::{sync::mpsc, time};
+use tracing::{error, info, warn};
 
 extern crate rust_stock_tracker_lib;
 use rust_stock_tracker_lib::*;
 
 macro_rules! exit {
+    ($code:expr, $template:tt) => {{
+        eprintln!($template);
+        std::process::exit($code);
+    }};
     ($code:expr, $template:tt, $($tt:tt)*) => {{
         eprintln!($template, $($tt)*);
         std::process::exit($code);
     }};
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Watch,
+    Once,
+    Backfill,
+    Export,
+    Replay,
+}
+
 struct Args {
+    command: Command,
     from: DateTime<Utc>,
+    to: Option<DateTime<Utc>>,
     symbols: Vec<String>,
+    validate: bool,
+    drop_invalid: bool,
     interval: time::Duration,
     debounce: time::Duration,
+    timeout: Option<time::Duration>,
+    source: Arc<dyn QuoteSource>,
+    fetch_workers: usize,
+    breaker: BreakerConfig,
+    repair: RepairConfig,
+    calendar: Option<Calendar>,
+    // the calendar indicators are checked against, if any
+    mark_gaps: Option<Calendar>,
+    schedule: Option<Schedule>,
+    indicators: Vec<String>,
+    timeframe: Option<Timeframe>,
+    transform_workers: usize,
+    store: Option<PathBuf>,
+    http: Option<String>,
+    output: Output,
+    tolerance: f64,
+    snapshot: Option<time::Duration>,
+    log_format: LogFormat,
+    log_filter: String,
+}
+
+fn parse_arg<T>(matches: &clap::ArgMatches, name: &str) -> Option<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    matches.value_of(name).map(|s| match s.parse() {
+        Ok(v) => v,
+        Err(e) => exit!(1, "Failed to parse {}: {}", name, e),
+    })
 }
 
 fn init() -> Args {
@@ -25,78 +75,628 @@ fn init() -> Args {
         (version: "0.1.0")
         (author: "Till Friesewinkel [till.friesewinkel@gmail.com]")
         (about: "Fetches stock prices from the Yahoo API")
-        (@arg from: +required "Starting date in %Y-%m-%d format")
-        (@arg symbols: +required "Ticker symbols for the stocks to fetch")
-        (@arg interval: -i --interval +takes_value "Interval between fetches in seconds (default: 30 seconds")
-        (@arg debounce: -d --debounce +takes_value "Minimum delay between initializing two requests (default: 15 ms)")
+        (setting: AppSettings::SubcommandRequiredElseHelp)
+        (@arg config: -C --config +takes_value +global "TOML file with settings, overridden by command line flags")
+        (@arg print_config: --("print-config") +global "Print the effective settings, defaults included, as TOML and exit")
+        (@arg symbols_file: -f --("symbols-file") +takes_value +multiple number_of_values(1) +global "File with comma- or newline-separated symbols, may be given multiple times")
+        (@arg universe: -u --universe +takes_value +global "Set expression over watchlists from the config and @files, e.g. 'sp500 - financials + @custom.txt'")
+        (@arg exclude: -x --exclude +takes_value +global "Comma-separated symbols to leave out")
+        (@arg validate: --validate +global "Check all symbols on startup and report unknown ones")
+        (@arg drop_invalid: --("drop-invalid") +global "Check all symbols on startup and drop unknown ones")
+        (@arg to: --to +takes_value +global "End date, in the same formats as the starting date (default: time of each fetch)")
+        (@arg interval: -i --interval +takes_value +global "Interval between fetches in seconds (default: 30 seconds")
+        (@arg debounce: -d --debounce +takes_value +global "Minimum delay between initializing two requests (default: 15 ms)")
+        (@arg timeout: --timeout +takes_value +global "Seconds to wait for a response before giving up on a request, 0 for no limit (default: 10)")
+        (@arg source: --source +takes_value +global "Quote client: 'yahoo' (yahoo_finance_api) or 'native' (pooled HTTP client) (default: native)")
+        (@arg base_url: --("base-url") +takes_value +global "Base URL of the native client, e.g. a local stand-in (default: Yahoo's chart API)")
+        (@arg transform_workers: --("transform-workers") +takes_value +global "Threads computing indicators, each for a share of the symbols (default: 1)")
+        (@arg timeframe: --timeframe +takes_value +global "Resample bars before computing indicators: e.g. '15m', '1h', '1w' or '1mo'")
+        (@arg fetch_workers: --("fetch-workers") +takes_value +global "Symbols fetched at a time (default: 32)")
+        (@arg store: -s --store +takes_value +global "Directory of Parquet files for backfill, export and replay")
+        (@arg http: --http +takes_value +global "Serve latest quotes and histories over HTTP on this address, e.g. 127.0.0.1:8080")
+        (@arg changes_only: -c --("changes-only") +global "Only print rows that changed since they were last printed")
+        (@arg tolerance: --tolerance +takes_value +global "Changes up to this value are not considered changes (default: 0)")
+        (@arg tui: -t --tui +global "Show an interactive dashboard instead of printing CSV")
+        (@arg snapshot: --snapshot +takes_value +global "With --changes-only, print all rows every this many seconds")
+        (@arg log_format: --("log-format") +takes_value +global "Log as 'human' readable lines or as 'json' (default: human)")
+        (@arg log_level: --("log-level") +takes_value +global "Log filter in RUST_LOG syntax, e.g. 'warn' or 'info,rust_stock_tracker_lib=debug' (default: RUST_LOG, or info)")
+        (@subcommand watch =>
+            (about: "Fetch and print quotes every interval")
+            (@arg from: "Starting date: YYYY-MM-DD, RFC 3339, or relative like 30d, 6mo, ytd")
+            (@arg symbols: "Ticker symbols for the stocks to fetch")
+        )
+        (@subcommand once =>
+            (about: "Fetch and print quotes once, then exit")
+            (@arg from: "Starting date: YYYY-MM-DD, RFC 3339, or relative like 30d, 6mo, ytd")
+            (@arg symbols: "Ticker symbols for the stocks to fetch")
+        )
+        (@subcommand backfill =>
+            (about: "Fetch quotes once and write them, with indicators, to the store")
+            (@arg from: "Starting date: YYYY-MM-DD, RFC 3339, or relative like 30d, 6mo, ytd")
+            (@arg symbols: "Ticker symbols for the stocks to fetch")
+        )
+        (@subcommand export =>
+            (about: "Print all quotes in the store as CSV")
+        )
+        (@subcommand replay =>
+            (about: "Run the quotes in the store through the transformer and print the results")
+        )
     )
     .get_matches();
 
-    let symbols = matches
-        .value_of("symbols")
-        .unwrap()
-        .split(",")
-        .map(|s| s.to_string())
-        .collect();
+    let (command, matches) = match matches.subcommand() {
+        ("watch", Some(m)) => (Command::Watch, m),
+        ("once", Some(m)) => (Command::Once, m),
+        ("backfill", Some(m)) => (Command::Backfill, m),
+        ("export", Some(m)) => (Command::Export, m),
+        ("replay", Some(m)) => (Command::Replay, m),
+        _ => unreachable!("clap requires a subcommand"),
+    };
 
-    let from = match DateTime::parse_from_rfc3339(matches.value_of("from").unwrap()) {
-        Err(e) => exit!(1, "Failed to parse start date: {}", e),
-        Ok(dt) => dt.with_timezone(&Utc),
+    let mut config = match matches.value_of("config") {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => exit!(1, "Failed to load config: {}", e),
+        },
+        None => Config::default(),
     };
 
-    let interval = time::Duration::from_secs(
-        matches
-            .value_of("interval")
-            .map(|s| match s.parse() {
-                Ok(i) => i,
-                Err(e) => exit!(1, "Failed to parse interval: {}", e),
-            })
-            .unwrap_or(30),
-    );
+    // command line flags take precedence over the config file
+    if let Some(from) = matches.value_of("from") {
+        config.from = Some(from.to_string());
+    }
+    if let Some(to) = matches.value_of("to") {
+        config.to = Some(to.to_string());
+    }
+    if matches.is_present("symbols") || matches.is_present("symbols_file") {
+        config.symbols = matches.value_of("symbols").map_or(vec![], parse_symbols);
+        config.symbol_files = matches
+            .values_of("symbols_file")
+            .map_or(vec![], |files| files.map(PathBuf::from).collect());
+        config.universe = None;
+    }
+    if let Some(universe) = matches.value_of("universe") {
+        config.universe = Some(universe.to_string());
+    }
+    if let Some(exclude) = matches.value_of("exclude") {
+        config.exclude = parse_symbols(exclude);
+    }
+    if matches.is_present("validate") {
+        config.validate = Some(true);
+    }
+    if matches.is_present("drop_invalid") {
+        config.drop_invalid = Some(true);
+    }
+    if let Some(interval) = parse_arg(matches, "interval") {
+        config.interval = Some(interval);
+    }
+    if let Some(debounce) = parse_arg(matches, "debounce") {
+        config.debounce = Some(debounce);
+    }
+    if let Some(timeout) = parse_arg(matches, "timeout") {
+        config.timeout = Some(timeout);
+    }
+    if let Some(source) = parse_arg(matches, "source") {
+        config.source = Some(source);
+    }
+    if let Some(base_url) = matches.value_of("base_url") {
+        config.base_url = Some(base_url.to_string());
+    }
+    if let Some(workers) = parse_arg(matches, "fetch_workers") {
+        config.fetch_workers = Some(workers);
+    }
+    if let Some(workers) = parse_arg(matches, "transform_workers") {
+        config.transform_workers = Some(workers);
+    }
+    if let Some(timeframe) = parse_arg(matches, "timeframe") {
+        config.timeframe = Some(timeframe);
+    }
+    if let Some(store) = matches.value_of("store") {
+        config.store = Some(store.into());
+    }
+    if let Some(http) = matches.value_of("http") {
+        config.sinks.http = Some(http.to_string());
+    }
+    if matches.is_present("changes_only") {
+        config.sinks.output = Some(Output::Changes);
+    }
+    if matches.is_present("tui") {
+        config.sinks.output = Some(Output::Tui);
+    }
+    if let Some(tolerance) = parse_arg(matches, "tolerance") {
+        config.sinks.tolerance = Some(tolerance);
+    }
+    if let Some(snapshot) = parse_arg(matches, "snapshot") {
+        config.sinks.snapshot = Some(snapshot);
+    }
+    if let Some(format) = parse_arg(matches, "log_format") {
+        config.log.format = Some(format);
+    }
+    if let Some(level) = matches.value_of("log_level") {
+        config.log.level = Some(level.to_string());
+    }
+
+    // everything below can rely on the defaults being filled in
+    let config = config.with_defaults();
+    if matches.is_present("print_config") {
+        print!("{}", config.to_toml());
+        std::process::exit(0);
+    }
 
-    let debounce = time::Duration::from_millis(
-        matches
-            .value_of("debounce")
-            .map(|s| match s.parse() {
-                Ok(i) => i,
-                Err(e) => exit!(1, "Failed to parse debounce: {}", e),
-            })
-            .unwrap_or(15),
+    let fetches = matches!(command, Command::Watch | Command::Once | Command::Backfill);
+    let stores = matches!(
+        command,
+        Command::Backfill | Command::Export | Command::Replay
     );
+    if stores && config.store.is_none() {
+        exit!(1, "No store given");
+    }
+
+    let now = Utc::now();
+    let from = match config.from.as_deref().map(|s| parse_date(s, now)) {
+        None if fetches => exit!(1, "No start date given"),
+        None => Utc.timestamp(0, 0),
+        Some(Err(e)) => exit!(1, "Failed to parse start date: {}", e),
+        Some(Ok(dt)) => dt,
+    };
+    let to = match config.to.as_deref().map(|s| parse_date(s, now)) {
+        None => None,
+        Some(Err(e)) => exit!(1, "Failed to parse end date: {}", e),
+        Some(Ok(dt)) if dt <= from => exit!(1, "End date {} is not after start date {}", dt, from),
+        Some(Ok(dt)) => Some(dt),
+    };
+
+    let interval = time::Duration::from_secs(config.interval.unwrap());
+
+    // a rate limit is just another way to express the debounce
+    let mut debounce = time::Duration::from_millis(config.debounce.unwrap());
+    if let Some(rps) = config.max_requests_per_second {
+        debounce = debounce.max(time::Duration::from_secs_f64(1.0 / rps));
+    }
+
+    let timeout = match config.timeout.unwrap() {
+        0 => None,
+        secs => Some(time::Duration::from_secs(secs)),
+    };
+
+    // one pooled client for all fetches, unless asked otherwise
+    let source_kind = config.source.unwrap();
+    let source: Arc<dyn QuoteSource> = match source_kind {
+        SourceKind::Yahoo => Arc::new(YahooSource::new()),
+        SourceKind::Native => match HttpSource::new() {
+            Ok(source) => match config.base_url.as_deref() {
+                Some(base_url) => Arc::new(source.with_base_url(base_url)),
+                None => Arc::new(source),
+            },
+            Err(e) => exit!(1, "Failed to set up HTTP client: {}", e),
+        },
+    };
+
+    // only commands that fetch need symbols and a start date
+    let symbols = match config.all_symbols(source.as_ref()) {
+        Ok(symbols) if fetches && symbols.is_empty() => exit!(1, "No symbols given"),
+        Ok(symbols) => symbols,
+        Err(e) => exit!(1, "Failed to read symbols: {}", e),
+    };
+
+    // the calendar knows trading days, which weekly or intraday bars are not
+    let mark_gaps = match config.mark_gaps {
+        Some(true) if config.timeframe.is_some() => {
+            eprintln!("Ignoring mark_gaps, gaps are only marked in daily bars");
+            None
+        }
+        Some(true) => config.calendar.clone(),
+        _ => None,
+    };
+    let output = config.sinks.output.unwrap();
+    // the dashboard owns the terminal, so stay quiet unless asked otherwise
+    let log_filter = config
+        .log
+        .level
+        .or_else(|| std::env::var("RUST_LOG").ok())
+        .unwrap_or_else(|| {
+            if output == Output::Tui && command == Command::Watch {
+                "off".to_string()
+            } else {
+                "info".to_string()
+            }
+        });
 
     Args {
+        command,
         from,
+        to,
         symbols,
+        validate: config.validate.unwrap(),
+        drop_invalid: config.drop_invalid.unwrap(),
         interval,
         debounce,
+        timeout,
+        source,
+        fetch_workers: config.fetch_workers.unwrap(),
+        breaker: config.breaker.unwrap(),
+        repair: config.repair.unwrap(),
+        mark_gaps,
+        calendar: config.calendar,
+        indicators: config.indicators.unwrap(),
+        timeframe: config.timeframe,
+        transform_workers: config.transform_workers.unwrap(),
+        schedule: config.schedule,
+        store: config.store,
+        http: config.sinks.http,
+        output,
+        tolerance: config.sinks.tolerance.unwrap(),
+        snapshot: config.sinks.snapshot.map(time::Duration::from_secs),
+        log_format: config.log.format.unwrap(),
+        log_filter,
+    }
+}
+
+/// Reports, and with `--drop-invalid` drops, symbols Yahoo does not know.
+async fn validate(fetcher: &mut Fetcher, drop_invalid: bool) {
+    let validation = fetcher.validate().await;
+    for e in validation.failed.iter() {
+        log_error(e);
+    }
+    if !validation.unknown.is_empty() {
+        warn!(
+            symbols = %validation.unknown.join(","),
+            dropped = drop_invalid,
+            "unknown or delisted symbols"
+        );
+        if drop_invalid {
+            fetcher.drop_symbols(&validation.unknown);
+        }
+    }
+}
+
+fn log_breaker_event(event: &BreakerEvent) {
+    if event.to == BreakerState::Open {
+        warn!(from = %event.from, failure_ratio = event.failure_ratio, "circuit open, pausing fetches");
+    } else {
+        info!(from = %event.from, to = %event.to, failure_ratio = event.failure_ratio, "circuit state changed");
     }
 }
 
+fn log_action(action: &CorporateAction) {
+    info!(symbol = %action.symbol, tick = action.tick, "{}", action);
+}
+
+fn log_repairs(report: &RepairReport) {
+    info!(symbol = %report.symbol, tick = report.tick, repairs = report.repairs.len(), "{}", report);
+}
+
+/// `fetch_rx` resampled to `timeframe`, if any.
+fn resampled(
+    fetch_rx: mpsc::Receiver<StockHistory>,
+    timeframe: Option<Timeframe>,
+    bufsize: usize,
+) -> mpsc::Receiver<StockHistory> {
+    match timeframe {
+        Some(timeframe) => {
+            let (resampler, resampled_rx) = Resampler::new(timeframe, bufsize);
+            subscribe(resampler.start(), fetch_rx);
+            resampled_rx
+        }
+        None => fetch_rx,
+    }
+}
+
+fn log_gaps(report: &GapReport) {
+    warn!(symbol = %report.symbol, tick = report.tick, gaps = report.gaps.len(), "{}", report);
+}
+
 #[actix_rt::main]
 async fn main() {
     let args = init();
-    let bufsize = args.symbols.len();
+    if let Err(e) = init_logging(args.log_format, &args.log_filter) {
+        exit!(1, "Failed to set up logging: {}", e);
+    }
+    match args.command {
+        Command::Watch => watch(args).await,
+        Command::Once | Command::Backfill => once(args).await,
+        Command::Export => export(args),
+        Command::Replay => replay(args).await,
+    }
+}
+
+async fn watch(args: Args) {
+    let metrics = Metrics::new().unwrap();
 
     let (ticker, tick_rx) = Ticker::new(args.interval, 5);
-    let ticker = ticker.start();
+    let ticker = ticker.with_schedule(args.schedule).start();
 
-    let (fetcher, fetch_rx, mut fetch_err_rx) =
+    let (fetcher, raw_fetch_rx, mut fetch_err_rx) =
         Fetcher::new(args.symbols, args.from, args.debounce);
-    let fetcher = fetcher.start();
-    subscribe(fetcher, tick_rx);
+    let mut fetcher = fetcher
+        .with_timeout(args.timeout)
+        .with_source(args.source)
+        .with_interval(
+            args.timeframe
+                .map_or("1d", |timeframe| timeframe.interval()),
+        )
+        .with_workers(args.fetch_workers);
+    if args.validate || args.drop_invalid {
+        validate(&mut fetcher, args.drop_invalid).await;
+    }
+    let bufsize = fetcher.symbols().len();
+    let err_tx = fetcher.error_sender();
+    let (breaker, mut breaker_rx) = CircuitBreaker::new(args.breaker);
+    let fetcher = fetcher
+        .with_to(args.to)
+        .with_metrics(metrics.clone())
+        .with_breaker(breaker)
+        .start();
+    subscribe_metered(fetcher, tick_rx, metrics.queue_depth("ticks"));
 
     let (transformer, info_rx) = Transformer::new(bufsize).unwrap();
-    let transformer = transformer.start();
-    subscribe(transformer, fetch_rx);
+    let transformer = transformer
+        .with_indicators(&args.indicators)
+        .with_calendar(args.mark_gaps.clone())
+        .with_errors(err_tx.clone())
+        .start_pool(args.transform_workers);
 
-    let printer = Printer.start();
-    subscribe(printer, info_rx);
+    let (tracker, mut action_rx) = ActionTracker::new(bufsize);
+    let (raw_fetch_rx, tracker_fetch_rx) = tee(raw_fetch_rx, bufsize);
+    subscribe(tracker.start(), tracker_fetch_rx);
+
+    // everything downstream sees the repaired bars
+    let (repairer, fetch_rx, mut report_rx) = Repairer::new(args.repair, bufsize);
+    let repairer = repairer.with_errors(err_tx).start();
+    subscribe_metered(repairer, raw_fetch_rx, metrics.queue_depth("fetched"));
+
+    // without a calendar the detector is dropped, which closes `gap_rx`
+    let (detector, mut gap_rx) =
+        GapDetector::new(args.calendar.clone().unwrap_or_default(), bufsize);
+    let fetch_rx = if args.calendar.is_some() {
+        let (fetch_rx, gap_fetch_rx) = tee(fetch_rx, bufsize);
+        subscribe(detector.start(), gap_fetch_rx);
+        fetch_rx
+    } else {
+        fetch_rx
+    };
+
+    let info_rx = match args.http {
+        None => {
+            let fetch_rx = resampled(fetch_rx, args.timeframe, bufsize);
+            subscribe_metered(transformer, fetch_rx, metrics.queue_depth("histories"));
+            info_rx
+        }
+        Some(addr) => {
+            let cache = Cache::new().start();
+            let hub = Hub::new(64).start();
+            let (fetch_rx, cache_fetch_rx) = tee(fetch_rx, bufsize);
+            let (info_rx, cache_info_rx) = tee(info_rx, bufsize);
+            let (cache_info_rx, hub_info_rx) = tee(cache_info_rx, bufsize);
+            let fetch_rx = resampled(fetch_rx, args.timeframe, bufsize);
+            subscribe_metered(transformer, fetch_rx, metrics.queue_depth("histories"));
+            subscribe(cache.clone(), cache_fetch_rx);
+            subscribe(cache.clone(), cache_info_rx);
+            subscribe(hub.clone(), hub_info_rx);
+            match serve(&addr, cache, hub, metrics.clone()) {
+                Ok(server) => actix::spawn(async move {
+                    if let Err(e) = server.await {
+                        error!(error = %e, "HTTP server failed");
+                    }
+                }),
+                Err(e) => exit!(1, "Failed to bind HTTP server to {}: {}", addr, e),
+            };
+            info_rx
+        }
+    };
+
+    if args.output == Output::Tui {
+        let (dashboard, mut quit_rx) = Dashboard::new(time::Duration::from_millis(250));
+        let dashboard = dashboard.start();
+        subscribe_metered(dashboard.clone(), info_rx, metrics.queue_depth("infos"));
+
+        let _ = ticker.send(StartTicking).await;
+
+        loop {
+            tokio::select! {
+                Some(err) = fetch_err_rx.recv() => {
+                    metrics.observe_error(&err);
+                    dashboard.do_send(err)
+                },
+                Some(event) = breaker_rx.recv() => dashboard.do_send(event),
+                Some(action) = action_rx.recv() => dashboard.do_send(action),
+                Some(report) = report_rx.recv() => {
+                    metrics.observe_repairs(&report);
+                    dashboard.do_send(report)
+                },
+                Some(report) = gap_rx.recv() => dashboard.do_send(report),
+                _ = quit_rx.recv() => return,
+            }
+        }
+    }
+
+    let printer = if args.output == Output::Changes {
+        Printer::changes_only(args.tolerance, args.snapshot)
+    } else {
+        Printer::new()
+    };
+    let printer = printer.start();
+    subscribe_metered(printer, info_rx, metrics.queue_depth("infos"));
 
     let _ = ticker.send(StartTicking).await;
 
     loop {
         tokio::select! {
-            Some(err) = fetch_err_rx.recv() => eprintln!("{}", err)
+            Some(err) = fetch_err_rx.recv() => {
+                metrics.observe_error(&err);
+                log_error(&err)
+            },
+            Some(event) = breaker_rx.recv() => log_breaker_event(&event),
+            Some(action) = action_rx.recv() => log_action(&action),
+            Some(report) = report_rx.recv() => {
+                metrics.observe_repairs(&report);
+                log_repairs(&report)
+            },
+            Some(report) = gap_rx.recv() => log_gaps(&report),
+        }
+    }
+}
+
+/// One fetch, then either print the results (`once`), or write them to the
+/// store (`backfill`).
+async fn once(args: Args) {
+    let exporter = match args.command {
+        Command::Backfill => Some(Exporter::new(args.store.unwrap()).start()),
+        _ => None,
+    };
+
+    let (fetcher, mut fetch_rx, mut fetch_err_rx) =
+        Fetcher::new(args.symbols, args.from, args.debounce);
+    let mut fetcher = fetcher
+        .with_timeout(args.timeout)
+        .with_source(args.source)
+        .with_interval(
+            args.timeframe
+                .map_or("1d", |timeframe| timeframe.interval()),
+        )
+        .with_workers(args.fetch_workers);
+    if args.validate || args.drop_invalid {
+        validate(&mut fetcher, args.drop_invalid).await;
+    }
+    let n_symbols = fetcher.symbols().len();
+    let err_tx = fetcher.error_sender();
+    let fetcher = fetcher.with_to(args.to).start();
+
+    let (transformer, mut info_rx) = Transformer::new(n_symbols).unwrap();
+    let transformer = transformer
+        .with_indicators(&args.indicators)
+        .with_calendar(args.mark_gaps.clone())
+        .with_errors(err_tx)
+        .start_pool(args.transform_workers);
+
+    let (mut tracker, _) = ActionTracker::new(1);
+    let (repairer, _, _) = Repairer::new(args.repair, 1);
+    let mut resampler = args
+        .timeframe
+        .map(|timeframe| Resampler::new(timeframe, 1).0);
+    let mut detector = args
+        .calendar
+        .map(|calendar| GapDetector::new(calendar, 1).0);
+
+    let _ = fetcher.send(StartFetch).await;
+    // route everything through here instead of `subscribe`, so that the
+    // exporter has seen every message by the time we flush
+    let mut done = 0;
+    while done < n_symbols {
+        tokio::select! {
+            Some(err) = fetch_err_rx.recv() => {
+                log_error(&err);
+                done += 1;
+            },
+            Some(history) = fetch_rx.recv() => {
+                tracker.observe(&history).iter().for_each(log_action);
+                let history = match repairer.repair(history) {
+                    Ok((history, report)) => {
+                        if !report.repairs.is_empty() {
+                            log_repairs(&report);
+                        }
+                        history
+                    }
+                    Err(e) => {
+                        log_error(&e);
+                        done += 1;
+                        continue;
+                    }
+                };
+                if let Some(report) = detector.as_mut().and_then(|d| d.observe(&history)) {
+                    log_gaps(&report);
+                }
+                if let Some(exporter) = exporter.as_ref() {
+                    exporter.send(history.clone()).await.unwrap();
+                }
+                let history = match resampler.as_mut() {
+                    Some(resampler) => resampler.resample(history),
+                    None => history,
+                };
+                transformer.send(history).await.unwrap();
+            },
+            Some(info) = info_rx.recv() => {
+                match exporter.as_ref() {
+                    Some(exporter) => exporter.send(info).await.unwrap(),
+                    None => println!("{}", info.fmt_csv()),
+                }
+                done += 1;
+            },
+        }
+    }
+
+    if let Some(exporter) = exporter {
+        match exporter.send(Flush).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                log_error(&Error::new(
+                    None,
+                    Stage::Sink,
+                    ErrorKind::Sink(e.to_string()),
+                ));
+                std::process::exit(1);
+            }
+            Err(e) => exit!(1, "Failed to reach exporter: {}", e),
+        }
+    }
+}
+
+fn read_store(args: &Args) -> Vec<StockHistory> {
+    let store = args.store.as_ref().unwrap();
+    let mut histories = match read_histories(store) {
+        Ok(histories) => histories,
+        Err(e) => exit!(1, "Failed to read store {}: {}", store.display(), e),
+    };
+    if !args.symbols.is_empty() {
+        let symbols: Vec<String> = args
+            .symbols
+            .iter()
+            .map(|s| args.source.normalize(s))
+            .collect();
+        histories.retain(|h| symbols.contains(&h.symbol));
+    }
+    histories
+}
+
+fn export(args: Args) {
+    println!("timestamp,symbol,open,high,low,close,adjclose,volume");
+    for history in read_store(&args) {
+        for q in history.quotes.iter() {
+            println!(
+                "{},{},{:.2},{:.2},{:.2},{:.2},{:.2},{}",
+                Utc.timestamp(q.timestamp as i64, 0).to_rfc3339(),
+                history.symbol,
+                q.open,
+                q.high,
+                q.low,
+                q.close,
+                q.adjclose,
+                q.volume
+            );
+        }
+    }
+}
+
+async fn replay(args: Args) {
+    let histories = read_store(&args);
+    let (transformer, mut info_rx) = Transformer::new(1).unwrap();
+    let transformer = transformer
+        .with_indicators(&args.indicators)
+        .with_calendar(args.mark_gaps)
+        .start();
+
+    let mut resampler = args
+        .timeframe
+        .map(|timeframe| Resampler::new(timeframe, 1).0);
+
+    for history in histories {
+        let history = match resampler.as_mut() {
+            Some(resampler) => resampler.resample(history),
+            None => history,
+        };
+        transformer.send(history).await.unwrap();
+        if let Some(info) = info_rx.recv().await {
+            println!("{}", info.fmt_csv());
         }
     }
 }
@@ -0,0 +1,279 @@
+use std::{collections::VecDeque, fmt, time::Duration};
+
+use actix::prelude::*;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, time::Instant};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BreakerConfig {
+    /// Share of failed fetches among the last `window` that opens the circuit.
+    pub failure_ratio: f64,
+    /// Fetches needed before the ratio is trusted.
+    pub min_requests: usize,
+    pub window: usize,
+    /// Seconds between probes while the circuit is open.
+    pub probe_interval: u64,
+}
+
+impl BreakerConfig {
+    /// Rejects windows that could never hold enough fetches to be trusted.
+    pub fn check(&self) -> Result<(), &'static str> {
+        if self.min_requests == 0 {
+            Err("`min_requests` must be at least 1")
+        } else if self.window < self.min_requests {
+            Err("`window` must be at least `min_requests`")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_ratio: 0.5,
+            min_requests: 20,
+            window: 100,
+            probe_interval: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    /// Everything is fetched.
+    Closed,
+    /// Nothing is fetched, except for a probe every `probe_interval`.
+    Open,
+    /// A probe is in flight, its outcome decides the next state.
+    HalfOpen,
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half-open",
+        })
+    }
+}
+
+/// Emitted whenever the circuit changes its state.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct BreakerEvent {
+    pub from: BreakerState,
+    pub to: BreakerState,
+    pub at: DateTime<Utc>,
+    /// Failure ratio over the window at the time of the change.
+    pub failure_ratio: f64,
+}
+
+/// What the breaker lets through on a tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admit {
+    All,
+    /// A single symbol, to find out whether the source has recovered.
+    Probe,
+    None,
+}
+
+/// Stops fetching from a quote source that fails most requests, instead of
+/// flooding the error stream with one error per symbol and tick.
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: BreakerState,
+    // outcomes of the latest fetches, `true` for failures
+    outcomes: VecDeque<bool>,
+    opened_at: Option<Instant>,
+    event_tx: mpsc::Sender<BreakerEvent>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> (Self, mpsc::Receiver<BreakerEvent>) {
+        let (event_tx, event_rx) = mpsc::channel(16);
+        let breaker = Self {
+            outcomes: VecDeque::with_capacity(config.window),
+            config,
+            state: BreakerState::Closed,
+            opened_at: None,
+            event_tx,
+        };
+        (breaker, event_rx)
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    /// What to fetch on a tick that starts at `now`.
+    pub fn admit(&mut self, now: Instant) -> Admit {
+        match self.state {
+            BreakerState::Closed => Admit::All,
+            BreakerState::HalfOpen => Admit::None,
+            BreakerState::Open => {
+                let probe_interval = Duration::from_secs(self.config.probe_interval);
+                if self.opened_at.is_none_or(|at| now >= at + probe_interval) {
+                    self.transition(BreakerState::HalfOpen);
+                    Admit::Probe
+                } else {
+                    Admit::None
+                }
+            }
+        }
+    }
+
+    /// Whether a fetch that was admitted earlier may still be sent, as the
+    /// circuit may have opened while it was waiting for its turn.
+    pub fn allows(&self, probe: bool) -> bool {
+        match self.state {
+            BreakerState::Closed => true,
+            BreakerState::HalfOpen => probe,
+            BreakerState::Open => false,
+        }
+    }
+
+    /// Records the outcome of a fetch. Only the `probe` decides whether a
+    /// half-open circuit closes or opens again.
+    pub fn record(&mut self, failed: bool, probe: bool, now: Instant) {
+        match self.state {
+            BreakerState::HalfOpen if probe && failed => {
+                self.opened_at = Some(now);
+                self.transition(BreakerState::Open);
+            }
+            BreakerState::HalfOpen if probe => {
+                self.outcomes.clear();
+                self.transition(BreakerState::Closed);
+            }
+            // stragglers from before the circuit opened
+            BreakerState::HalfOpen | BreakerState::Open => {}
+            BreakerState::Closed => {
+                if self.outcomes.len() == self.config.window {
+                    self.outcomes.pop_front();
+                }
+                self.outcomes.push_back(failed);
+                if self.outcomes.len() >= self.config.min_requests
+                    && self.failure_ratio() >= self.config.failure_ratio
+                {
+                    self.opened_at = Some(now);
+                    self.transition(BreakerState::Open);
+                }
+            }
+        }
+    }
+
+    /// Reopens the circuit after `admit` allowed a probe that could not be
+    /// sent, so that the next tick probes again instead of waiting for an
+    /// outcome that never comes.
+    pub fn cancel_probe(&mut self) {
+        if self.state == BreakerState::HalfOpen {
+            self.transition(BreakerState::Open);
+        }
+    }
+
+    pub fn failure_ratio(&self) -> f64 {
+        if self.outcomes.is_empty() {
+            return 0.0;
+        }
+        self.outcomes.iter().filter(|&&failed| failed).count() as f64 / self.outcomes.len() as f64
+    }
+
+    fn transition(&mut self, to: BreakerState) {
+        let event = BreakerEvent {
+            from: self.state,
+            to,
+            at: Utc::now(),
+            failure_ratio: self.failure_ratio(),
+        };
+        self.state = to;
+        // state changes are rare, a full buffer means nobody is listening
+        let _ = self.event_tx.try_send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        assert!(BreakerConfig::default().check().is_ok());
+        let config = |min_requests, window| BreakerConfig {
+            min_requests,
+            window,
+            ..BreakerConfig::default()
+        };
+        assert!(config(1, 1).check().is_ok());
+        assert!(config(0, 10).check().is_err());
+        assert!(config(20, 10).check().is_err());
+    }
+
+    #[test]
+    fn breaker() {
+        let (mut breaker, mut event_rx) = CircuitBreaker::new(BreakerConfig {
+            failure_ratio: 0.5,
+            min_requests: 4,
+            window: 4,
+            probe_interval: 60,
+        });
+        let start = Instant::now();
+
+        // not enough requests yet, then a ratio below the threshold
+        breaker.record(true, false, start);
+        breaker.record(false, false, start);
+        breaker.record(false, false, start);
+        assert_eq!(breaker.admit(start), Admit::All);
+        breaker.record(false, false, start);
+        breaker.record(false, false, start);
+        breaker.record(true, false, start);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+
+        // the window slides, 2 of the last 4 failed
+        breaker.record(true, false, start);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(event_rx.try_recv().unwrap().to, BreakerState::Open);
+        assert_eq!(breaker.admit(start + Duration::from_secs(59)), Admit::None);
+
+        // a failed probe opens the circuit again, a successful one closes it
+        let later = start + Duration::from_secs(60);
+        assert_eq!(breaker.admit(later), Admit::Probe);
+        assert_eq!(breaker.admit(later), Admit::None);
+        assert!(breaker.allows(true));
+        assert!(!breaker.allows(false));
+        // a fetch from before the circuit opened decides nothing
+        breaker.record(false, false, later);
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        breaker.record(true, true, later);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(breaker.admit(later), Admit::None);
+
+        // a probe that was never sent, the next one is due right away
+        let even_later = later + Duration::from_secs(60);
+        assert_eq!(breaker.admit(even_later), Admit::Probe);
+        breaker.cancel_probe();
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        assert_eq!(breaker.admit(even_later), Admit::Probe);
+        breaker.record(false, true, even_later);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.failure_ratio(), 0.0);
+
+        let states: Vec<_> = std::iter::from_fn(|| event_rx.try_recv().ok())
+            .map(|e| e.to)
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                BreakerState::HalfOpen,
+                BreakerState::Open,
+                BreakerState::HalfOpen,
+                BreakerState::Open,
+                BreakerState::HalfOpen,
+                BreakerState::Closed
+            ]
+        );
+    }
+}
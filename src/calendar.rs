@@ -0,0 +1,229 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+};
+
+use actix::prelude::*;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use yahoo_finance_api::Quote;
+
+use crate::messages::*;
+
+/// The days an exchange trades on, in its local time.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Calendar {
+    pub days: Vec<Weekday>,
+    /// Days without trading besides those not in `days`, e.g. public
+    /// holidays.
+    pub holidays: Vec<NaiveDate>,
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Self {
+            days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+            holidays: vec![],
+        }
+    }
+}
+
+impl Calendar {
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        self.days.contains(&date.weekday()) && !self.holidays.contains(&date)
+    }
+
+    /// Trading days from the first to the last of `dates` without an entry in
+    /// `dates`, which must be ordered.
+    pub fn gaps(&self, dates: &[NaiveDate]) -> Vec<NaiveDate> {
+        let (first, last) = match (dates.first(), dates.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return vec![],
+        };
+        let present: HashSet<&NaiveDate> = dates.iter().collect();
+        let mut gaps = vec![];
+        let mut date = first;
+        while date <= last {
+            if self.is_trading_day(date) && !present.contains(&date) {
+                gaps.push(date);
+            }
+            date += chrono::Duration::days(1);
+        }
+        gaps
+    }
+}
+
+/// Dates of `quotes` in the exchange's local time, or in UTC if the exchange
+/// is not known. Intraday bars of the same day share a date.
+pub fn bar_dates(quotes: &[Quote], instrument: Option<&Instrument>) -> Vec<NaiveDate> {
+    let mut dates: Vec<NaiveDate> = quotes
+        .iter()
+        .map(|q| {
+            let at = Utc.timestamp(q.timestamp as i64, 0);
+            match instrument {
+                Some(instrument) => instrument.local_time(at).naive_local().date(),
+                None => at.naive_utc().date(),
+            }
+        })
+        .collect();
+    dates.dedup();
+    dates
+}
+
+/// Trading days without bars that a history has not been reported for.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct GapReport {
+    pub symbol: String,
+    pub tick: u64,
+    pub gaps: Vec<NaiveDate>,
+}
+
+impl fmt::Display for GapReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dates: Vec<String> = self
+            .gaps
+            .iter()
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .collect();
+        write!(
+            f,
+            "{} has no bars for {} trading day(s): {}",
+            self.symbol,
+            dates.len(),
+            dates.join(", ")
+        )
+    }
+}
+
+/// Compares the bars of each history against a `Calendar`, and reports each
+/// trading day without a bar once.
+pub struct GapDetector {
+    calendar: Calendar,
+    report_tx: mpsc::Sender<GapReport>,
+    // gaps reported so far, and the latest known metadata, per symbol
+    seen: HashMap<String, BTreeSet<NaiveDate>>,
+    instruments: HashMap<String, Instrument>,
+}
+
+impl GapDetector {
+    pub fn new(calendar: Calendar, bufsize: usize) -> (Self, mpsc::Receiver<GapReport>) {
+        let (report_tx, report_rx) = mpsc::channel(bufsize);
+        let detector = Self {
+            calendar,
+            report_tx,
+            seen: HashMap::new(),
+            instruments: HashMap::new(),
+        };
+        (detector, report_rx)
+    }
+
+    /// The gaps in `history` that have not been reported before, if any.
+    pub fn observe(&mut self, history: &StockHistory) -> Option<GapReport> {
+        if let Some(instrument) = history.instrument.as_ref() {
+            self.instruments
+                .insert(history.symbol.clone(), instrument.clone());
+        }
+        let dates = bar_dates(&history.quotes, self.instruments.get(&history.symbol));
+        let seen = self.seen.entry(history.symbol.clone()).or_default();
+        let gaps: Vec<NaiveDate> = self
+            .calendar
+            .gaps(&dates)
+            .into_iter()
+            .filter(|date| seen.insert(*date))
+            .collect();
+        if gaps.is_empty() {
+            return None;
+        }
+        Some(GapReport {
+            symbol: history.symbol.clone(),
+            tick: history.tick,
+            gaps,
+        })
+    }
+}
+
+impl Actor for GapDetector {
+    type Context = Context<Self>;
+}
+
+impl Handler<StockHistory> for GapDetector {
+    type Result = ();
+
+    fn handle(&mut self, history: StockHistory, _: &mut Context<Self>) {
+        if let Some(report) = self.observe(&history) {
+            let tx = self.report_tx.clone();
+            actix::spawn(async move {
+                let _ = tx.send(report).await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps() {
+        let calendar = Calendar {
+            holidays: vec![NaiveDate::from_ymd(2021, 10, 11)],
+            ..Calendar::default()
+        };
+        // Fri 8th to Thu 14th, without the weekend, the holiday on Monday and
+        // Wednesday
+        let dates: Vec<NaiveDate> = [8, 12, 14]
+            .iter()
+            .map(|&day| NaiveDate::from_ymd(2021, 10, day))
+            .collect();
+        assert_eq!(
+            calendar.gaps(&dates),
+            vec![NaiveDate::from_ymd(2021, 10, 13)]
+        );
+        assert!(calendar.gaps(&[]).is_empty());
+    }
+
+    #[test]
+    fn detector() {
+        let (mut detector, _) = GapDetector::new(Calendar::default(), 1);
+        let bar = |day: u32| Quote {
+            // 9:30 in New York
+            timestamp: Utc.ymd(2021, 10, day).and_hms(13, 30, 0).timestamp() as u64,
+            open: 1.0,
+            high: 1.0,
+            low: 1.0,
+            volume: 1,
+            close: 1.0,
+            adjclose: 1.0,
+        };
+        let mut history = StockHistory {
+            symbol: "AAPL".to_string(),
+            quotes: vec![bar(11), bar(13)],
+            missing: vec![],
+            instrument: None,
+            splits: vec![],
+            dividends: vec![],
+            from: Utc.ymd(2021, 10, 11).and_hms(0, 0, 0),
+            tick: 1,
+        };
+        assert_eq!(
+            detector.observe(&history).unwrap().to_string(),
+            "AAPL has no bars for 1 trading day(s): 2021-10-12"
+        );
+        assert!(detector.observe(&history).is_none());
+
+        history.quotes.push(bar(15));
+        assert_eq!(
+            detector.observe(&history).unwrap().gaps,
+            vec![NaiveDate::from_ymd(2021, 10, 14)]
+        );
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    breaker::BreakerConfig,
+    calendar::Calendar,
+    logging::LogFormat,
+    quotes::QuoteSource,
+    repair::{Issue, Policy, RepairConfig},
+    resample::Timeframe,
+    symbols::*,
+    ticker::Schedule,
+    yfetch::FETCH_WORKERS,
+};
+
+/// Settings read from a TOML file. Every value is optional, so that the file
+/// can be combined with command line flags, which take precedence. Relative
+/// paths in the file are relative to the file itself.
+///
+/// ```toml
+/// symbols = ["AAPL", "MSFT"]
+/// symbol_files = ["sp500.txt"]   # comma- or newline-separated, `#` comments
+/// universe = "sp500 - financials + custom + @more.txt"
+/// exclude = ["ALXN"]
+/// validate = true                # check symbols on startup
+/// drop_invalid = true            # and drop unknown ones
+/// from = "2021-09-01"            # or RFC 3339, or relative: 30d, 6mo, ytd
+/// to = "2021-10-01"
+/// interval = 30                  # seconds
+/// debounce = 15                  # milliseconds
+/// timeout = 10                   # seconds per request, 0 for none
+/// source = "native"              # default, or "yahoo", see `SourceKind`
+/// base_url = "http://127.0.0.1:8000/chart"  # native only, default is Yahoo
+/// max_requests_per_second = 50
+/// fetch_workers = 32             # symbols fetched at a time
+/// indicators = ["sma30", "rsi14"]  # default: sma30, ema20, rsi14 and atr14
+/// transform_workers = 4         # threads computing indicators, default 1
+/// timeframe = "1w"              # resample bars first: "15m", "1h", "1w" or "1mo"
+/// mark_gaps = true               # mark SMA30s over days without bars with `*`, daily bars only
+/// store = "quotes/"              # used by backfill, export and replay
+///
+/// [watchlists]
+/// sp500 = { files = ["sp500.txt"] }
+/// financials = { symbols = ["JPM", "BAC", "C", "WFC"] }
+/// custom = { symbols = ["UBER"], files = ["custom.txt"] }
+///
+/// [sinks]
+/// output = "changes"             # "csv", "changes" or "tui"
+/// tolerance = 0.01
+/// snapshot = 600                 # seconds
+/// http = "127.0.0.1:8080"
+///
+/// [breaker]                      # stop fetching while most fetches fail
+/// failure_ratio = 0.5            # of the last `window` fetches
+/// min_requests = 20
+/// window = 100
+/// probe_interval = 60            # seconds
+///
+/// [repair]                       # per issue: "drop", "forward_fill" or "error"
+/// missing = "drop"               # bars without a close
+/// out_of_order = "sort"          # "sort", "drop" or "error"
+/// duplicate = "drop"             # keeps the first bar, "keep_last" the last
+/// high_below_low = "drop"
+/// non_positive = "drop"
+/// spike = "drop"
+/// spike_threshold = 0.5          # relative change of the close
+///
+/// [calendar]                     # report trading days without bars
+/// days = ["Mon", "Tue", "Wed", "Thu", "Fri"]  # in exchange local time
+/// holidays = ["2021-11-25", "2021-12-24"]
+///
+/// [log]
+/// format = "json"                # or "human"
+/// level = "info,rust_stock_tracker_lib=debug"  # `RUST_LOG` syntax
+///
+/// [schedule]                     # UTC
+/// days = ["Mon", "Tue", "Wed", "Thu", "Fri"]
+/// start = "13:30:00"
+/// end = "20:00:00"
+/// ```
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub symbols: Vec<String>,
+    pub symbol_files: Vec<PathBuf>,
+    pub universe: Option<String>,
+    pub exclude: Vec<String>,
+    pub validate: Option<bool>,
+    pub drop_invalid: Option<bool>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub interval: Option<u64>,
+    pub debounce: Option<u64>,
+    pub timeout: Option<u64>,
+    pub source: Option<SourceKind>,
+    pub base_url: Option<String>,
+    pub max_requests_per_second: Option<f64>,
+    pub fetch_workers: Option<usize>,
+    pub indicators: Option<Vec<String>>,
+    pub transform_workers: Option<usize>,
+    pub timeframe: Option<Timeframe>,
+    pub mark_gaps: Option<bool>,
+    pub store: Option<PathBuf>,
+    pub watchlists: BTreeMap<String, Watchlist>,
+    pub sinks: Sinks,
+    pub breaker: Option<BreakerConfig>,
+    pub repair: Option<RepairConfig>,
+    pub calendar: Option<Calendar>,
+    pub log: Log,
+    pub schedule: Option<Schedule>,
+}
+
+/// A named list of symbols, which can be used in `universe` expressions.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Watchlist {
+    pub symbols: Vec<String>,
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sinks {
+    pub output: Option<Output>,
+    pub tolerance: Option<f64>,
+    pub snapshot: Option<u64>,
+    pub http: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    pub format: Option<LogFormat>,
+    pub level: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    Csv,
+    Changes,
+    Tui,
+}
+
+/// The client the `Fetcher` uses.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    /// `YahooSource`, through `yahoo_finance_api`.
+    Yahoo,
+    /// `HttpSource`, with a shared connection pool.
+    Native,
+}
+
+impl FromStr for SourceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yahoo" => Ok(SourceKind::Yahoo),
+            "native" => Ok(SourceKind::Native),
+            _ => Err(format!("expected `yahoo` or `native`, got `{}`", s)),
+        }
+    }
+}
+
+/// Indicators the `Transformer` knows how to compute.
+pub const INDICATORS: [&str; 4] = ["sma30", "ema20", "rsi14", "atr14"];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    UnknownIndicator(String),
+    UnknownWatchlist(String),
+    /// An issue with a policy that does not apply to it.
+    Repair(Issue, Policy),
+    Breaker(&'static str),
+    Universe(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Parse(e) => write!(f, "{}", e),
+            ConfigError::UnknownIndicator(name) => write!(
+                f,
+                "unknown indicator `{}`, expected one of: {}",
+                name,
+                INDICATORS.join(", ")
+            ),
+            ConfigError::UnknownWatchlist(name) => write!(f, "unknown watchlist `{}`", name),
+            ConfigError::Repair(issue, policy) => write!(
+                f,
+                "policy `{}` does not apply to `{}`",
+                policy.as_str(),
+                issue.as_str()
+            ),
+            ConfigError::Breaker(e) => write!(f, "invalid breaker: {}", e),
+            ConfigError::Universe(e) => write!(f, "invalid universe: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.into();
+        let content = fs::read_to_string(&path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        let mut config = Self::parse(&content)?;
+        config.resolve_paths(path.parent().unwrap_or_else(|| Path::new("")));
+        Ok(config)
+    }
+
+    /// Makes the relative paths of symbol files, watchlist files, `@file`s in
+    /// the universe and the store relative to `dir` instead.
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = dir.join(&path);
+            }
+        };
+        self.symbol_files.iter_mut().for_each(resolve);
+        for watchlist in self.watchlists.values_mut() {
+            watchlist.files.iter_mut().for_each(resolve);
+        }
+        self.store.iter_mut().for_each(resolve);
+
+        // an invalid expression is reported when it is evaluated
+        let universe = self.universe.as_deref().map(parse_set_expression);
+        if let Some(Ok(operands)) = universe {
+            let mut expr = String::new();
+            for (i, (op, operand)) in operands.into_iter().enumerate() {
+                match (i, op) {
+                    (0, _) => {}
+                    (_, SetOp::Union) => expr.push_str(" + "),
+                    (_, SetOp::Exclude) => expr.push_str(" - "),
+                }
+                match operand.strip_prefix('@') {
+                    Some(path) if Path::new(path).is_relative() => {
+                        expr.push('@');
+                        expr.push_str(&dir.join(path).to_string_lossy());
+                    }
+                    _ => expr.push_str(operand),
+                }
+            }
+            self.universe = Some(expr);
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(content).map_err(ConfigError::Parse)?;
+        for name in config.indicators.iter().flatten() {
+            if !INDICATORS.contains(&name.as_str()) {
+                return Err(ConfigError::UnknownIndicator(name.clone()));
+            }
+        }
+        if let Some(repair) = config.repair.as_ref() {
+            repair
+                .check()
+                .map_err(|(issue, policy)| ConfigError::Repair(issue, policy))?;
+        }
+        if let Some(breaker) = config.breaker.as_ref() {
+            breaker.check().map_err(ConfigError::Breaker)?;
+        }
+        Ok(config)
+    }
+
+    /// All symbols: the inline ones first, followed by those from the symbol
+    /// files and the universe, without the excluded ones. Symbols are
+    /// normalized for `source` before they are combined, so that `BRK.B`
+    /// excludes `BRK-B`.
+    pub fn all_symbols(&self, source: &dyn QuoteSource) -> Result<Vec<String>, ConfigError> {
+        let mut symbols = vec![];
+        union(&mut symbols, normalize(&self.symbols, source));
+        for path in self.symbol_files.iter() {
+            union(&mut symbols, read_file(path, source)?);
+        }
+        if let Some(universe) = self.universe.as_ref() {
+            union(&mut symbols, self.eval_universe(universe, source)?);
+        }
+        exclude(&mut symbols, &normalize(&self.exclude, source));
+        Ok(symbols)
+    }
+
+    /// Evaluates a set expression over watchlist names and `@file`s from left
+    /// to right, e.g. `sp500 - financials + @custom.txt`.
+    pub fn eval_universe(
+        &self,
+        expr: &str,
+        source: &dyn QuoteSource,
+    ) -> Result<Vec<String>, ConfigError> {
+        let mut symbols = vec![];
+        for (op, operand) in parse_set_expression(expr).map_err(ConfigError::Universe)? {
+            let operand = match operand.strip_prefix('@') {
+                Some(path) => read_file(Path::new(path), source)?,
+                None => self.watchlist(operand, source)?,
+            };
+            match op {
+                SetOp::Union => union(&mut symbols, operand),
+                SetOp::Exclude => exclude(&mut symbols, &operand),
+            }
+        }
+        Ok(symbols)
+    }
+
+    fn watchlist(&self, name: &str, source: &dyn QuoteSource) -> Result<Vec<String>, ConfigError> {
+        let watchlist = self
+            .watchlists
+            .get(name)
+            .ok_or_else(|| ConfigError::UnknownWatchlist(name.to_string()))?;
+        let mut symbols = normalize(&watchlist.symbols, source);
+        for path in watchlist.files.iter() {
+            union(&mut symbols, read_file(path, source)?);
+        }
+        Ok(symbols)
+    }
+
+    pub fn has_indicator(&self, name: &str) -> bool {
+        self.indicators
+            .as_ref()
+            .is_none_or(|indicators| indicators.iter().any(|i| i == name))
+    }
+
+    /// Fills in the defaults of all settings that have one, i.e. all but the
+    /// dates, the symbols, the store and the optional features.
+    pub fn with_defaults(mut self) -> Self {
+        self.validate.get_or_insert(false);
+        self.drop_invalid.get_or_insert(false);
+        self.interval.get_or_insert(30);
+        self.debounce.get_or_insert(15);
+        self.timeout.get_or_insert(10);
+        self.source.get_or_insert(SourceKind::Native);
+        self.fetch_workers.get_or_insert(FETCH_WORKERS);
+        self.indicators
+            .get_or_insert_with(|| INDICATORS.iter().map(|i| i.to_string()).collect());
+        self.transform_workers.get_or_insert(1);
+        self.mark_gaps.get_or_insert(false);
+        self.breaker.get_or_insert_with(BreakerConfig::default);
+        self.repair.get_or_insert_with(RepairConfig::default);
+        self.sinks.output.get_or_insert(Output::Csv);
+        self.sinks.tolerance.get_or_insert(0.0);
+        self.log.format.get_or_insert(LogFormat::Human);
+        self
+    }
+
+    /// The configuration as TOML, with only the values that have been set,
+    /// see `with_defaults`.
+    pub fn to_toml(&self) -> String {
+        // all our values are representable in TOML
+        toml::to_string_pretty(self).unwrap()
+    }
+}
+
+fn normalize(symbols: &[String], source: &dyn QuoteSource) -> Vec<String> {
+    symbols.iter().map(|s| source.normalize(s)).collect()
+}
+
+fn read_file(path: &Path, source: &dyn QuoteSource) -> Result<Vec<String>, ConfigError> {
+    read_symbols(path)
+        .map(|symbols| normalize(&symbols, source))
+        .map_err(|e| ConfigError::Io(path.to_path_buf(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quotes::YahooSource;
+
+    #[test]
+    fn parse() {
+        let config = Config::parse(
+            r#"
+            symbols = ["AAPL"]
+            interval = 10
+            indicators = []
+
+            [sinks]
+            output = "changes"
+
+            [schedule]
+            days = ["Mon", "Fri"]
+            start = "13:30:00"
+            end = "20:00:00"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.symbols, vec!["AAPL"]);
+        assert_eq!(config.interval, Some(10));
+        assert_eq!(config.debounce, None);
+        assert_eq!(config.sinks.output, Some(Output::Changes));
+        assert!(!config.has_indicator("sma30"));
+        assert!(config.schedule.is_some());
+
+        // the round trip yields the same settings
+        let config = Config::parse(&config.to_toml()).unwrap();
+        assert_eq!(config.interval, Some(10));
+        assert_eq!(config.sinks.output, Some(Output::Changes));
+
+        assert!(Config::default().has_indicator("sma30"));
+        let config = Config::parse(&Config::default().with_defaults().to_toml()).unwrap();
+        assert_eq!(config.interval, Some(30));
+        assert!(config.has_indicator("sma30"));
+        assert!(config.breaker.is_some());
+        assert!(Config::parse("indicators = [\"rsi\"]").is_err());
+        assert!(Config::parse("intervall = 10").is_err());
+        assert!(Config::parse("[repair]\nout_of_order = \"forward_fill\"").is_err());
+        assert!(Config::parse("[breaker]\nmin_requests = 0").is_err());
+        assert!(Config::parse("[breaker]\nmin_requests = 20\nwindow = 10").is_err());
+    }
+
+    #[test]
+    fn universe() {
+        let config = Config::parse(
+            r#"
+            symbols = ["UBER"]
+            universe = "large - banks + custom"
+            exclude = ["GOOG"]
+
+            [watchlists]
+            large = { symbols = ["AAPL", "GOOG", "JPM", "BAC"] }
+            banks = { symbols = ["JPM", "BAC", "C"] }
+            custom = { symbols = ["C", "UBER", "IBM"] }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.all_symbols(&YahooSource::new()).unwrap(),
+            vec!["UBER", "AAPL", "C", "IBM"]
+        );
+        assert!(config
+            .eval_universe("large - unknown", &YahooSource::new())
+            .is_err());
+
+        // share classes are spelled the same before they are compared
+        let config = Config::parse(
+            r#"
+            symbols = ["BRK.B", "brk-a", "AAPL"]
+            exclude = ["BRK-B", "BRK.A"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.all_symbols(&YahooSource::new()).unwrap(),
+            vec!["AAPL"]
+        );
+        assert!(config
+            .eval_universe("large banks", &YahooSource::new())
+            .is_err());
+    }
+
+    #[test]
+    fn relative_paths() {
+        let mut config = Config::parse(
+            r#"
+            symbol_files = ["sp500.txt", "/etc/symbols.txt"]
+            universe = "large - @banks.txt + @/tmp/custom.txt"
+            store = "quotes"
+
+            [watchlists]
+            large = { files = ["large.txt"] }
+            "#,
+        )
+        .unwrap();
+        config.resolve_paths(Path::new("conf"));
+        assert_eq!(
+            config.symbol_files,
+            vec![PathBuf::from("conf/sp500.txt"), "/etc/symbols.txt".into()]
+        );
+        assert_eq!(
+            config.universe.as_deref(),
+            Some("large - @conf/banks.txt + @/tmp/custom.txt")
+        );
+        assert_eq!(config.store, Some("conf/quotes".into()));
+        assert_eq!(
+            config.watchlists["large"].files,
+            vec![PathBuf::from("conf/large.txt")]
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    io, panic,
+    sync::atomic::{AtomicBool, Ordering as AtomicOrdering},
+};
+
+use actix::prelude::*;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use tokio::{sync::mpsc, time};
+use tracing::error;
+use tui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, List, ListItem, Row, Table},
+    Terminal,
+};
+
+use crate::{
+    breaker::BreakerEvent, calendar::GapReport, error::Error, messages::*, repair::RepairReport,
+};
+
+const SPARKLINE_LEN: usize = 20;
+const MAX_EVENTS: usize = 100;
+// whether the terminal is in raw mode on the alternate screen, shared with the
+// panic hook
+static TERMINAL_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+const COLUMNS: [&str; 7] = [
+    "Symbol", "Price", "Change %", "High", "Low", "SMA30", "Trend",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortBy {
+    Symbol,
+    Price,
+    Change,
+    High,
+    Low,
+    Sma30,
+}
+
+impl SortBy {
+    fn next(self) -> Self {
+        match self {
+            SortBy::Symbol => SortBy::Price,
+            SortBy::Price => SortBy::Change,
+            SortBy::Change => SortBy::High,
+            SortBy::High => SortBy::Low,
+            SortBy::Low => SortBy::Sma30,
+            SortBy::Sma30 => SortBy::Symbol,
+        }
+    }
+
+    fn column(self) -> usize {
+        self as usize
+    }
+
+    fn compare(self, a: &StockInfo, b: &StockInfo) -> Ordering {
+        let by = |x: f64, y: f64| x.partial_cmp(&y).unwrap_or(Ordering::Equal);
+        match self {
+            SortBy::Symbol => a.symbol.cmp(&b.symbol),
+            SortBy::Price => by(a.price, b.price),
+            SortBy::Change => by(a.close, b.close),
+            SortBy::High => by(a.high, b.high),
+            SortBy::Low => by(a.low, b.low),
+            SortBy::Sma30 => by(
+                a.sma30.unwrap_or(f64::NEG_INFINITY),
+                b.sma30.unwrap_or(f64::NEG_INFINITY),
+            ),
+        }
+    }
+}
+
+struct Entry {
+    info: StockInfo,
+    prices: VecDeque<f64>,
+}
+
+/// Interactive terminal view of the latest `StockInfo` per symbol, with errors
+/// and other events in a side panel. `s` cycles the sort column, `r` reverses the order
+/// and `q`, `Esc` or `Ctrl-C` quit.
+pub struct Dashboard {
+    refresh: time::Duration,
+    quit_tx: mpsc::Sender<()>,
+    terminal: Option<Terminal<CrosstermBackend<io::Stdout>>>,
+    entries: HashMap<String, Entry>,
+    events: VecDeque<String>,
+    sort_by: SortBy,
+    reversed: bool,
+}
+
+impl Dashboard {
+    /// The returned receiver yields once the dashboard has been closed and the
+    /// terminal restored.
+    pub fn new(refresh: time::Duration) -> (Self, mpsc::Receiver<()>) {
+        let (quit_tx, quit_rx) = mpsc::channel(1);
+        let dashboard = Self {
+            refresh,
+            quit_tx,
+            terminal: None,
+            entries: HashMap::new(),
+            events: VecDeque::new(),
+            sort_by: SortBy::Symbol,
+            reversed: false,
+        };
+        (dashboard, quit_rx)
+    }
+
+    fn push_event(&mut self, e: String) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_back();
+        }
+        self.events.push_front(e);
+    }
+
+    /// Entries in the order they are shown in.
+    fn sorted(&self) -> Vec<&Entry> {
+        let mut entries: Vec<&Entry> = self.entries.values().collect();
+        entries.sort_by(|a, b| self.sort_by.compare(&a.info, &b.info));
+        if self.reversed {
+            entries.reverse();
+        }
+        entries
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let entries = self.sorted();
+        let header = Row::new(COLUMNS.iter().enumerate().map(|(i, title)| {
+            let style = Style::default().add_modifier(Modifier::BOLD);
+            if i == self.sort_by.column() {
+                Cell::from(*title).style(style.add_modifier(Modifier::UNDERLINED))
+            } else {
+                Cell::from(*title).style(style)
+            }
+        }));
+        let rows = entries.into_iter().map(|entry| {
+            let info = &entry.info;
+            let color = match info.close.partial_cmp(&0.0) {
+                Some(Ordering::Greater) => Color::Green,
+                Some(Ordering::Less) => Color::Red,
+                _ => Color::Reset,
+            };
+            Row::new(vec![
+                info.symbol.clone(),
+                match info.instrument.as_ref() {
+                    Some(instrument) => format!("{:.2} {}", info.price, instrument.currency),
+                    None => format!("{:.2}", info.price),
+                },
+                format!("{:+.2}", info.close),
+                format!("{:.2}", info.high),
+                format!("{:.2}", info.low),
+                info.fmt_sma30(),
+                sparkline(&entry.prices),
+            ])
+            .style(Style::default().fg(color))
+        });
+        let table = Table::new(rows)
+            .header(header)
+            .block(Block::default().borders(Borders::ALL).title("Quotes"))
+            .widths(&[
+                Constraint::Length(8),
+                // price and currency
+                Constraint::Length(14),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Length(SPARKLINE_LEN as u16),
+            ]);
+
+        let events: Vec<ListItem> = self
+            .events
+            .iter()
+            .map(|e| ListItem::new(e.as_str()))
+            .collect();
+        let events = List::new(events)
+            .block(Block::default().borders(Borders::ALL).title("Events"))
+            .style(Style::default().fg(Color::Yellow));
+
+        if let Some(terminal) = self.terminal.as_mut() {
+            terminal.draw(|f| {
+                let chunks = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+                    .split(f.size());
+                f.render_widget(table, chunks[0]);
+                f.render_widget(events, chunks[1]);
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Actor for Dashboard {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        TERMINAL_ACTIVE.store(true, AtomicOrdering::SeqCst);
+        let terminal = terminal::enable_raw_mode()
+            .and_then(|_| execute!(io::stdout(), EnterAlternateScreen))
+            .and_then(|_| Terminal::new(CrosstermBackend::new(io::stdout())));
+        match terminal {
+            Ok(terminal) => self.terminal = Some(terminal),
+            Err(e) => {
+                restore_terminal();
+                error!(sink = "dashboard", error = %e, "failed to set up terminal");
+                ctx.stop();
+                return;
+            }
+        }
+
+        // a panic anywhere would otherwise leave the shell in raw mode
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            hook(info)
+        }));
+
+        // raw mode turns Ctrl-C into a key press, but SIGINT can still be sent
+        // from elsewhere
+        ctx.spawn(
+            async {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+            .into_actor(self)
+            .map(|_, _, ctx| ctx.stop()),
+        );
+
+        // crossterm only offers blocking reads
+        let addr = ctx.address();
+        std::thread::spawn(move || {
+            while addr.connected() {
+                match event::read() {
+                    Ok(Event::Key(key)) => addr.do_send(Key(key)),
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+        });
+
+        ctx.run_interval(self.refresh, |dashboard, ctx| {
+            if let Err(e) = dashboard.draw() {
+                dashboard
+                    .events
+                    .push_front(format!("Failed to draw: {}", e));
+                ctx.stop();
+            }
+        });
+    }
+
+    fn stopped(&mut self, _: &mut Context<Self>) {
+        self.terminal = None;
+        restore_terminal();
+        let _ = self.quit_tx.try_send(());
+    }
+}
+
+impl Handler<StockInfo> for Dashboard {
+    type Result = ();
+
+    fn handle(&mut self, info: StockInfo, _: &mut Context<Self>) {
+        let entry = self
+            .entries
+            .entry(info.symbol.clone())
+            .or_insert_with(|| Entry {
+                info: info.clone(),
+                prices: VecDeque::with_capacity(SPARKLINE_LEN),
+            });
+        if entry.prices.len() == SPARKLINE_LEN {
+            entry.prices.pop_front();
+        }
+        entry.prices.push_back(info.price);
+        entry.info = info;
+    }
+}
+
+impl Handler<Error> for Dashboard {
+    type Result = ();
+
+    fn handle(&mut self, e: Error, _: &mut Context<Self>) {
+        self.push_event(e.to_string());
+    }
+}
+
+impl Handler<BreakerEvent> for Dashboard {
+    type Result = ();
+
+    fn handle(&mut self, e: BreakerEvent, _: &mut Context<Self>) {
+        self.push_event(format!(
+            "{} circuit {} (failure ratio {:.2})",
+            e.at.to_rfc3339(),
+            e.to,
+            e.failure_ratio
+        ));
+    }
+}
+
+impl Handler<CorporateAction> for Dashboard {
+    type Result = ();
+
+    fn handle(&mut self, action: CorporateAction, _: &mut Context<Self>) {
+        self.push_event(action.to_string());
+    }
+}
+
+impl Handler<RepairReport> for Dashboard {
+    type Result = ();
+
+    fn handle(&mut self, report: RepairReport, _: &mut Context<Self>) {
+        self.push_event(report.to_string());
+    }
+}
+
+impl Handler<GapReport> for Dashboard {
+    type Result = ();
+
+    fn handle(&mut self, report: GapReport, _: &mut Context<Self>) {
+        self.push_event(report.to_string());
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Key(KeyEvent);
+
+impl Handler<Key> for Dashboard {
+    type Result = ();
+
+    fn handle(&mut self, Key(key): Key, ctx: &mut Context<Self>) {
+        if quits(&key) {
+            ctx.stop();
+            return;
+        }
+        match key.code {
+            KeyCode::Char('s') => self.sort_by = self.sort_by.next(),
+            KeyCode::Char('r') => self.reversed = !self.reversed,
+            _ => {}
+        }
+    }
+}
+
+fn quits(key: &KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        _ => false,
+    }
+}
+
+// leaves raw mode and the alternate screen, once, whichever way the dashboard
+// goes away
+fn restore_terminal() {
+    if TERMINAL_ACTIVE.swap(false, AtomicOrdering::SeqCst) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+    }
+}
+
+fn sparkline(prices: &VecDeque<f64>) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let min = prices.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = prices.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    prices
+        .iter()
+        .map(|p| {
+            if max > min {
+                BARS[((p - min) / (max - min) * 7.0).round() as usize]
+            } else {
+                BARS[3]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    fn info(symbol: &str, price: f64, sma30: Option<f64>) -> StockInfo {
+        StockInfo {
+            symbol: symbol.to_string(),
+            from: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            timestamp: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            open: 1.0,
+            high: price,
+            low: price,
+            close: price - 10.0,
+            price,
+            sma30,
+            sma30_gapped: false,
+            ema20: None,
+            rsi14: None,
+            atr14: None,
+            instrument: None,
+            tick: 0,
+        }
+    }
+
+    #[test]
+    fn sort() {
+        let (mut dashboard, _) = Dashboard::new(time::Duration::from_secs(1));
+        for info in [
+            info("MSFT", 300.0, Some(290.0)),
+            info("AAPL", 150.0, None),
+            info("GOOG", 2800.0, Some(2700.0)),
+        ] {
+            dashboard.entries.insert(
+                info.symbol.clone(),
+                Entry {
+                    info,
+                    prices: VecDeque::new(),
+                },
+            );
+        }
+        let symbols = |dashboard: &Dashboard| -> Vec<String> {
+            dashboard
+                .sorted()
+                .iter()
+                .map(|e| e.info.symbol.clone())
+                .collect()
+        };
+
+        assert_eq!(symbols(&dashboard), vec!["AAPL", "GOOG", "MSFT"]);
+        dashboard.sort_by = dashboard.sort_by.next();
+        assert_eq!(dashboard.sort_by.column(), 1);
+        assert_eq!(symbols(&dashboard), vec!["AAPL", "MSFT", "GOOG"]);
+        dashboard.reversed = true;
+        assert_eq!(symbols(&dashboard), vec!["GOOG", "MSFT", "AAPL"]);
+        // without an SMA30 sorts first
+        dashboard.reversed = false;
+        dashboard.sort_by = SortBy::Sma30;
+        assert_eq!(symbols(&dashboard), vec!["AAPL", "MSFT", "GOOG"]);
+        assert!(dashboard.sort_by.next() == SortBy::Symbol);
+    }
+
+    #[test]
+    fn sparklines() {
+        let prices = |prices: &[f64]| prices.iter().cloned().collect::<VecDeque<_>>();
+        assert_eq!(sparkline(&prices(&[])), "");
+        assert_eq!(sparkline(&prices(&[5.0, 5.0])), "▄▄");
+        assert_eq!(
+            sparkline(&prices(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0])),
+            "▁▂▃▄▅▆▇█"
+        );
+        // scaled to the range of the prices shown
+        assert_eq!(sparkline(&prices(&[100.0, 110.0, 105.0])), "▁█▅");
+    }
+
+    #[test]
+    fn quit_keys() {
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        assert!(quits(&key(KeyCode::Char('q'), KeyModifiers::NONE)));
+        assert!(quits(&key(KeyCode::Esc, KeyModifiers::NONE)));
+        assert!(quits(&key(KeyCode::Char('c'), KeyModifiers::CONTROL)));
+        assert!(!quits(&key(KeyCode::Char('c'), KeyModifiers::NONE)));
+        assert!(!quits(&key(KeyCode::Char('s'), KeyModifiers::CONTROL)));
+    }
+}
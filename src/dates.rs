@@ -0,0 +1,128 @@
+use std::fmt;
+
+use chrono::prelude::*;
+use chrono::Duration;
+
+#[derive(Debug, PartialEq)]
+pub struct DateError(String);
+
+impl fmt::Display for DateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid date `{}`, expected YYYY-MM-DD, an RFC 3339 timestamp \
+             or a relative date like 30d, 2w, 6mo, 1y or ytd",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for DateError {}
+
+/// Parses a date given as
+///
+/// - `YYYY-MM-DD`, meaning midnight UTC,
+/// - an RFC 3339 timestamp, e.g. `2021-09-01T00:00:00Z`,
+/// - `today` or `ytd` (start of the year), relative to `now`,
+/// - a number of days (`30d`), weeks (`2w`), months (`6mo`) or years (`1y`)
+///   before `now`.
+pub fn parse_date(s: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, DateError> {
+    let s = s.trim();
+    let invalid = || DateError(s.to_string());
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(DateTime::from_utc(date.and_hms(0, 0, 0), Utc));
+    }
+
+    match s {
+        "today" => return Ok(now.date().and_hms(0, 0, 0)),
+        "ytd" => return Ok(Utc.ymd(now.year(), 1, 1).and_hms(0, 0, 0)),
+        _ => {}
+    }
+
+    let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (n, unit) = s.split_at(split);
+    let n: u32 = n.parse().map_err(|_| invalid())?;
+    match unit {
+        "d" => Ok(now - Duration::days(n as i64)),
+        "w" => Ok(now - Duration::weeks(n as i64)),
+        "mo" => months_before(now, n).ok_or_else(invalid),
+        "y" => months_before(now, n * 12).ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+// same day of month, clamped to the length of the target month
+fn months_before(dt: DateTime<Utc>, months: u32) -> Option<DateTime<Utc>> {
+    let total = dt.year() * 12 + dt.month0() as i32 - months as i32;
+    let (year, month) = (total.div_euclid(12), total.rem_euclid(12) as u32 + 1);
+    let day = (1..=dt.day())
+        .rev()
+        .find(|&day| NaiveDate::from_ymd_opt(year, month, day).is_some())?;
+    Some(DateTime::from_utc(
+        NaiveDate::from_ymd_opt(year, month, day)?.and_time(dt.time()),
+        Utc,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn absolute() {
+        let now = Utc::now();
+        let midnight = Utc.ymd(2021, 9, 1).and_hms(0, 0, 0);
+        assert_eq!(parse_date("2021-09-01", now), Ok(midnight));
+        assert_eq!(parse_date("2021-09-01T00:00:00Z", now), Ok(midnight));
+        assert_eq!(parse_date("2021-09-01T02:00:00+02:00", now), Ok(midnight));
+    }
+
+    #[test]
+    fn relative() {
+        let now = Utc.ymd(2021, 10, 31).and_hms(12, 0, 0);
+        assert_eq!(
+            parse_date("today", now),
+            Ok(Utc.ymd(2021, 10, 31).and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            parse_date("ytd", now),
+            Ok(Utc.ymd(2021, 1, 1).and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            parse_date("30d", now),
+            Ok(Utc.ymd(2021, 10, 1).and_hms(12, 0, 0))
+        );
+        assert_eq!(
+            parse_date("2w", now),
+            Ok(Utc.ymd(2021, 10, 17).and_hms(12, 0, 0))
+        );
+        assert_eq!(
+            parse_date("6mo", now),
+            Ok(Utc.ymd(2021, 4, 30).and_hms(12, 0, 0))
+        );
+        assert_eq!(
+            parse_date("12mo", now),
+            Ok(Utc.ymd(2020, 10, 31).and_hms(12, 0, 0))
+        );
+        assert_eq!(
+            parse_date("1y", now),
+            Ok(Utc.ymd(2020, 10, 31).and_hms(12, 0, 0))
+        );
+        assert_eq!(
+            parse_date("8mo", now),
+            Ok(Utc.ymd(2021, 2, 28).and_hms(12, 0, 0))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let now = Utc::now();
+        for s in &["", "d", "30", "30x", "-3d", "2021-13-01", "yesterday"] {
+            assert_eq!(parse_date(s, now), Err(DateError(s.to_string())));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+/// How long resolved addresses are reused. The system resolver does not tell
+/// the TTL of its records, and Yahoo's hardly change within minutes.
+pub const DNS_TTL: Duration = Duration::from_secs(300);
+
+// addresses per host, and when they were resolved
+type Entries = HashMap<String, (Vec<SocketAddr>, Instant)>;
+
+/// Counters of a `DnsCache`, see `ConnectionStats::since` for the numbers of
+/// a single batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// New connections, each of which costs a TLS handshake for https URLs.
+    pub connections: u64,
+    /// Lookups that went to the system resolver.
+    pub dns_lookups: u64,
+}
+
+impl ConnectionStats {
+    pub fn since(&self, earlier: &ConnectionStats) -> ConnectionStats {
+        ConnectionStats {
+            connections: self.connections - earlier.connections,
+            dns_lookups: self.dns_lookups - earlier.dns_lookups,
+        }
+    }
+}
+
+/// Resolves host names through the system resolver and keeps the addresses
+/// for `ttl`, so that a burst of new connections costs a single lookup.
+///
+/// hyper resolves the host for every new connection, but not for requests on
+/// pooled ones, so this also counts connections.
+#[derive(Clone)]
+pub struct DnsCache {
+    ttl: Duration,
+    entries: Arc<Mutex<Entries>>,
+    // held during lookups, so that concurrent misses wait for the first one
+    lookup: Arc<tokio::sync::Mutex<()>>,
+    connections: Arc<AtomicU64>,
+    lookups: Arc<AtomicU64>,
+}
+
+impl DnsCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            lookup: Arc::new(tokio::sync::Mutex::new(())),
+            connections: Arc::new(AtomicU64::new(0)),
+            lookups: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            connections: self.connections.load(Ordering::Relaxed),
+            dns_lookups: self.lookups.load(Ordering::Relaxed),
+        }
+    }
+
+    fn cached(&self, host: &str) -> Option<Vec<SocketAddr>> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(host) {
+            Some((addrs, at)) if at.elapsed() < self.ttl => Some(addrs.clone()),
+            _ => None,
+        }
+    }
+
+    async fn lookup(&self, host: &str) -> std::io::Result<Vec<SocketAddr>> {
+        if let Some(addrs) = self.cached(host) {
+            return Ok(addrs);
+        }
+        let _lookup = self.lookup.lock().await;
+        if let Some(addrs) = self.cached(host) {
+            return Ok(addrs);
+        }
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        // hyper sets the port of the URL on each address
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(host.to_string(), (addrs.clone(), Instant::now()));
+        Ok(addrs)
+    }
+}
+
+impl Resolve for DnsCache {
+    fn resolve(&self, name: Name) -> Resolving {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        let cache = self.clone();
+        Box::pin(async move {
+            let addrs = cache.lookup(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn cache() {
+        let cache = DnsCache::new(DNS_TTL);
+        assert!(!cache.lookup("localhost").await.unwrap().is_empty());
+        assert!(!cache.lookup("localhost").await.unwrap().is_empty());
+        assert_eq!(cache.stats().dns_lookups, 1);
+
+        let expired = DnsCache::new(Duration::from_secs(0));
+        expired.lookup("localhost").await.unwrap();
+        expired.lookup("localhost").await.unwrap();
+        assert_eq!(expired.stats().dns_lookups, 2);
+    }
+}
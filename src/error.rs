@@ -0,0 +1,162 @@
+use std::{fmt, time::Duration};
+
+use actix::prelude::*;
+use chrono::prelude::*;
+use yahoo_finance_api::YahooError;
+
+use crate::{quotes::HttpError, repair::Issue};
+
+/// The part of the pipeline an error occurred in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Fetch,
+    Parse,
+    Validate,
+    Transform,
+    Sink,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Fetch => "fetch",
+            Stage::Parse => "parse",
+            Stage::Validate => "validate",
+            Stage::Transform => "transform",
+            Stage::Sink => "sink",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Yahoo(YahooError),
+    Http(HttpError),
+    /// No response within the configured timeout.
+    Timeout(Duration),
+    EmptyHistory,
+    /// A bar with an issue whose policy is `Policy::Error`, and its timestamp.
+    Invalid(Issue, u64),
+    Sink(String),
+}
+
+impl ErrorKind {
+    /// Low-cardinality name, e.g. for metric labels. Errors of a source are
+    /// named after their variant, invalid bars after their issue.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Yahoo(e) => yahoo_error_name(e),
+            ErrorKind::Http(e) => e.name(),
+            ErrorKind::Timeout(_) => "Timeout",
+            ErrorKind::EmptyHistory => "EmptyHistory",
+            ErrorKind::Invalid(issue, _) => issue.as_str(),
+            ErrorKind::Sink(_) => "Sink",
+        }
+    }
+}
+
+fn yahoo_error_name(e: &YahooError) -> &'static str {
+    match e {
+        YahooError::FetchFailed(_) => "FetchFailed",
+        YahooError::DeserializeFailed(_) => "DeserializeFailed",
+        YahooError::ConnectionFailed => "ConnectionFailed",
+        YahooError::InvalidJson => "InvalidJson",
+        YahooError::EmptyDataSet => "EmptyDataSet",
+        YahooError::DataInconsistency => "DataInconsistency",
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Yahoo(e) => write!(f, "{}", e),
+            ErrorKind::Http(e) => write!(f, "{}", e),
+            ErrorKind::Timeout(timeout) => write!(f, "no response within {:?}", timeout),
+            ErrorKind::EmptyHistory => write!(f, "history contains no quotes"),
+            ErrorKind::Invalid(issue, timestamp) => write!(
+                f,
+                "{} bar at {}",
+                issue,
+                Utc.timestamp(*timestamp as i64, 0).to_rfc3339()
+            ),
+            ErrorKind::Sink(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// An error anywhere in the pipeline. All stages report to the same stream,
+/// see `Fetcher::error_sender`.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Error {
+    pub symbol: Option<String>,
+    pub stage: Stage,
+    /// Number of consecutive failures for this symbol and stage, starting at 1.
+    pub attempt: u32,
+    /// Tick of the fetch the error belongs to, if any.
+    pub tick: Option<u64>,
+    pub at: DateTime<Utc>,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    pub fn new(symbol: Option<String>, stage: Stage, kind: ErrorKind) -> Self {
+        Self {
+            symbol,
+            stage,
+            attempt: 1,
+            tick: None,
+            at: Utc::now(),
+            kind,
+        }
+    }
+
+    pub fn with_attempt(mut self, attempt: u32) -> Self {
+        self.attempt = attempt;
+        self
+    }
+
+    pub fn with_tick(mut self, tick: u64) -> Self {
+        self.tick = Some(tick);
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.at.to_rfc3339(), self.stage)?;
+        if let Some(symbol) = self.symbol.as_ref() {
+            write!(f, " {}", symbol)?;
+        }
+        if self.attempt > 1 {
+            write!(f, " (attempt {})", self.attempt)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let yahoo = ErrorKind::Yahoo(YahooError::FetchFailed("404 Not Found".into()));
+        assert_eq!(yahoo.name(), "FetchFailed");
+        assert_eq!(ErrorKind::Http(HttpError::Status(429)).name(), "Status");
+        assert_eq!(
+            ErrorKind::Http(HttpError::Dns("no such host".into())).name(),
+            "Dns"
+        );
+        assert_eq!(ErrorKind::Invalid(Issue::Spike, 0).name(), "spike");
+        assert_eq!(ErrorKind::Timeout(Duration::from_secs(1)).name(), "Timeout");
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use actix::prelude::*;
+use arrow::{
+    array::{
+        ArrayRef, Float64Array, StringArray, TimestampMillisecondArray, TimestampSecondArray,
+        UInt64Array,
+    },
+    datatypes::{DataType, Field, Schema, TimeUnit},
+    record_batch::RecordBatch,
+};
+use chrono::prelude::*;
+use parquet::{arrow::ArrowWriter, errors::Result};
+use tracing::{info, trace};
+use yahoo_finance_api as yahoo;
+
+use crate::messages::*;
+
+/// Accumulates quote histories and computed indicators, and writes them as
+/// Parquet files, partitioned by symbol and the date of the bar they are of,
+/// on `Flush`:
+///
+/// ```text
+/// <dir>/quotes/symbol=AAPL/date=2021-09-01/part-0.parquet
+/// <dir>/indicators/symbol=AAPL/date=2021-09-01/part-0.parquet
+/// ```
+pub struct Exporter {
+    dir: PathBuf,
+    histories: HashMap<String, StockHistory>,
+    infos: HashMap<String, Vec<(DateTime<Utc>, StockInfo)>>,
+}
+
+impl Exporter {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            histories: HashMap::new(),
+            infos: HashMap::new(),
+        }
+    }
+
+    fn write_quotes(&self, history: &StockHistory) -> Result<()> {
+        let mut by_date: BTreeMap<NaiveDate, Vec<&yahoo::Quote>> = BTreeMap::new();
+        for q in history.quotes.iter() {
+            let date = Utc.timestamp(q.timestamp as i64, 0).date().naive_utc();
+            by_date.entry(date).or_default().push(q);
+        }
+
+        for (date, quotes) in by_date {
+            let path = partition(&self.dir, "quotes", &history.symbol, date);
+            write_batch(&path, quotes_batch(&history.symbol, &quotes)?)?;
+        }
+        Ok(())
+    }
+
+    fn write_infos(&self, symbol: &str, infos: &[(DateTime<Utc>, StockInfo)]) -> Result<()> {
+        let mut by_date: BTreeMap<NaiveDate, Vec<&(DateTime<Utc>, StockInfo)>> = BTreeMap::new();
+        for entry in infos.iter() {
+            by_date
+                .entry(entry.1.timestamp.date().naive_utc())
+                .or_default()
+                .push(entry);
+        }
+
+        for (date, infos) in by_date {
+            let path = partition(&self.dir, "indicators", symbol, date);
+            write_batch(&path, infos_batch(symbol, &infos)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl Actor for Exporter {
+    type Context = Context<Self>;
+}
+
+impl Handler<StockHistory> for Exporter {
+    type Result = ();
+
+    fn handle(&mut self, history: StockHistory, _: &mut Context<Self>) {
+        trace!(sink = "exporter", tick = history.tick, symbol = %history.symbol, "received");
+        // each fetch covers the whole period since `from`, so the latest
+        // history supersedes all previous ones
+        self.histories.insert(history.symbol.clone(), history);
+    }
+}
+
+impl Handler<StockInfo> for Exporter {
+    type Result = ();
+
+    fn handle(&mut self, info: StockInfo, _: &mut Context<Self>) {
+        trace!(sink = "exporter", tick = info.tick, symbol = %info.symbol, "received");
+        self.infos
+            .entry(info.symbol.clone())
+            .or_default()
+            .push((Utc::now(), info));
+    }
+}
+
+impl Handler<Flush> for Exporter {
+    type Result = Result<()>;
+
+    fn handle(&mut self, _: Flush, _: &mut Context<Self>) -> Self::Result {
+        for history in self.histories.values() {
+            self.write_quotes(history)?;
+        }
+        for (symbol, infos) in self.infos.iter() {
+            self.write_infos(symbol, infos)?;
+        }
+        info!(
+            sink = "exporter",
+            histories = self.histories.len(),
+            symbols = self.infos.len(),
+            dir = %self.dir.display(),
+            "flushed"
+        );
+        Ok(())
+    }
+}
+
+fn partition(dir: &Path, table: &str, symbol: &str, date: NaiveDate) -> PathBuf {
+    dir.join(table)
+        .join(format!("symbol={}", symbol))
+        .join(format!("date={}", date.format("%Y-%m-%d")))
+        .join("part-0.parquet")
+}
+
+fn utc_timestamp(unit: TimeUnit) -> DataType {
+    DataType::Timestamp(unit, Some("UTC".to_string()))
+}
+
+fn quotes_batch(symbol: &str, quotes: &[&yahoo::Quote]) -> Result<RecordBatch> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("timestamp", utc_timestamp(TimeUnit::Second), false),
+        Field::new("open", DataType::Float64, false),
+        Field::new("high", DataType::Float64, false),
+        Field::new("low", DataType::Float64, false),
+        Field::new("close", DataType::Float64, false),
+        Field::new("adjclose", DataType::Float64, false),
+        Field::new("volume", DataType::UInt64, false),
+    ]));
+
+    let f64_column = |f: fn(&yahoo::Quote) -> f64| -> ArrayRef {
+        Arc::new(Float64Array::from(
+            quotes.iter().map(|q| f(q)).collect::<Vec<_>>(),
+        ))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec![symbol; quotes.len()])),
+        Arc::new(TimestampSecondArray::from_vec(
+            quotes.iter().map(|q| q.timestamp as i64).collect(),
+            Some("UTC".to_string()),
+        )),
+        f64_column(|q| q.open),
+        f64_column(|q| q.high),
+        f64_column(|q| q.low),
+        f64_column(|q| q.close),
+        f64_column(|q| q.adjclose),
+        Arc::new(UInt64Array::from(
+            quotes.iter().map(|q| q.volume).collect::<Vec<_>>(),
+        )),
+    ];
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+fn infos_batch(symbol: &str, infos: &[&(DateTime<Utc>, StockInfo)]) -> Result<RecordBatch> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("computed_at", utc_timestamp(TimeUnit::Millisecond), false),
+        Field::new("from", utc_timestamp(TimeUnit::Second), false),
+        Field::new("timestamp", utc_timestamp(TimeUnit::Second), false),
+        Field::new("open", DataType::Float64, false),
+        Field::new("high", DataType::Float64, false),
+        Field::new("low", DataType::Float64, false),
+        Field::new("change_pct", DataType::Float64, false),
+        Field::new("price", DataType::Float64, false),
+        Field::new("sma30", DataType::Float64, true),
+        Field::new("ema20", DataType::Float64, true),
+        Field::new("rsi14", DataType::Float64, true),
+        Field::new("atr14", DataType::Float64, true),
+    ]));
+
+    let f64_column = |f: fn(&StockInfo) -> f64| -> ArrayRef {
+        Arc::new(Float64Array::from(
+            infos.iter().map(|(_, info)| f(info)).collect::<Vec<_>>(),
+        ))
+    };
+    let indicator_column = |f: fn(&StockInfo) -> Option<f64>| -> ArrayRef {
+        Arc::new(Float64Array::from(
+            infos.iter().map(|(_, info)| f(info)).collect::<Vec<_>>(),
+        ))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec![symbol; infos.len()])),
+        Arc::new(TimestampMillisecondArray::from_vec(
+            infos.iter().map(|(at, _)| at.timestamp_millis()).collect(),
+            Some("UTC".to_string()),
+        )),
+        Arc::new(TimestampSecondArray::from_vec(
+            infos
+                .iter()
+                .map(|(_, info)| info.from.timestamp())
+                .collect(),
+            Some("UTC".to_string()),
+        )),
+        Arc::new(TimestampSecondArray::from_vec(
+            infos
+                .iter()
+                .map(|(_, info)| info.timestamp.timestamp())
+                .collect(),
+            Some("UTC".to_string()),
+        )),
+        f64_column(|info| info.open),
+        f64_column(|info| info.high),
+        f64_column(|info| info.low),
+        f64_column(|info| info.close),
+        f64_column(|info| info.price),
+        indicator_column(|info| info.sma30),
+        indicator_column(|info| info.ema20),
+        indicator_column(|info| info.rsi14),
+        indicator_column(|info| info.atr14),
+    ];
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+fn write_batch(path: &Path, batch: RecordBatch) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = fs::File::create(path)?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::read_histories;
+
+    fn quote(at: DateTime<Utc>, close: f64) -> yahoo::Quote {
+        yahoo::Quote {
+            timestamp: at.timestamp() as u64,
+            open: close,
+            high: close,
+            low: close,
+            volume: 100,
+            close,
+            adjclose: close,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("export-round-trip-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let day = Utc.ymd(2021, 9, 1).and_hms(13, 30, 0);
+        let quotes: Vec<_> = (0..3)
+            .map(|i| quote(day + chrono::Duration::days(i), 10.0 + i as f64))
+            .collect();
+        let history = StockHistory {
+            symbol: "AAPL".to_string(),
+            from: day,
+            quotes: quotes.clone(),
+            missing: vec![],
+            instrument: None,
+            splits: vec![],
+            dividends: vec![],
+            tick: 1,
+        };
+        let info = StockInfo {
+            symbol: "AAPL".to_string(),
+            from: day,
+            timestamp: Utc.timestamp(quotes[2].timestamp as i64, 0),
+            open: 10.0,
+            high: 12.0,
+            low: 10.0,
+            close: 20.0,
+            price: 12.0,
+            sma30: None,
+            sma30_gapped: false,
+            ema20: None,
+            rsi14: None,
+            atr14: None,
+            instrument: None,
+            tick: 1,
+        };
+
+        let exporter = Exporter::new(&dir).start();
+        exporter.send(history).await.unwrap();
+        exporter.send(info).await.unwrap();
+        exporter.send(Flush).await.unwrap().unwrap();
+
+        // filed under the date of the latest bar, not of today
+        assert!(dir
+            .join("indicators/symbol=AAPL/date=2021-09-03/part-0.parquet")
+            .exists());
+        let histories = read_histories(&dir).unwrap();
+        assert_eq!(histories.len(), 1);
+        assert_eq!(histories[0].symbol, "AAPL");
+        assert_eq!(histories[0].from, day);
+        assert_eq!(
+            histories[0]
+                .quotes
+                .iter()
+                .map(|q| (q.timestamp, q.close, q.volume))
+                .collect::<Vec<_>>(),
+            quotes
+                .iter()
+                .map(|q| (q.timestamp, q.close, q.volume))
+                .collect::<Vec<_>>()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+
+use yahoo_finance_api::Quote;
+
+/// Running state of an indicator, fed one value at a time, so that a new bar
+/// costs O(1) regardless of how long the history is. Most are fed closes,
+/// those that need the range of a bar are fed the whole bar.
+pub trait Indicator<T = f64>: Clone {
+    fn update(&mut self, value: T);
+    /// `None` until enough values have been seen.
+    fn value(&self) -> Option<f64>;
+}
+
+/// Simple moving average over the last `period` values, from a rolling sum.
+#[derive(Debug, Clone)]
+pub struct Sma {
+    period: usize,
+    window: VecDeque<f64>,
+    sum: f64,
+}
+
+impl Sma {
+    pub fn new(period: usize) -> Self {
+        Self {
+            period,
+            window: VecDeque::with_capacity(period + 1),
+            sum: 0.0,
+        }
+    }
+}
+
+impl Indicator for Sma {
+    fn update(&mut self, value: f64) {
+        self.window.push_back(value);
+        self.sum += value;
+        if self.window.len() > self.period {
+            self.sum -= self.window.pop_front().unwrap();
+        }
+    }
+
+    fn value(&self) -> Option<f64> {
+        if self.window.len() < self.period {
+            return None;
+        }
+        Some(self.sum / self.period as f64)
+    }
+}
+
+/// Exponential moving average, seeded with the simple average of the first
+/// `period` values.
+#[derive(Debug, Clone)]
+pub struct Ema {
+    period: usize,
+    alpha: f64,
+    seen: usize,
+    value: f64,
+}
+
+impl Ema {
+    /// Weighs each new value with `2 / (period + 1)`.
+    pub fn new(period: usize) -> Self {
+        Self::with_alpha(period, 2.0 / (period as f64 + 1.0))
+    }
+
+    /// Wilder's smoothing, as used by RSI and ATR, which weighs each new value
+    /// with `1 / period`.
+    pub fn wilder(period: usize) -> Self {
+        Self::with_alpha(period, 1.0 / period as f64)
+    }
+
+    fn with_alpha(period: usize, alpha: f64) -> Self {
+        Self {
+            period,
+            alpha,
+            seen: 0,
+            value: 0.0,
+        }
+    }
+}
+
+impl Indicator for Ema {
+    fn update(&mut self, value: f64) {
+        self.seen += 1;
+        if self.seen <= self.period {
+            // the running mean of the seed values
+            self.value += (value - self.value) / self.seen as f64;
+        } else {
+            self.value += self.alpha * (value - self.value);
+        }
+    }
+
+    fn value(&self) -> Option<f64> {
+        if self.seen < self.period {
+            return None;
+        }
+        Some(self.value)
+    }
+}
+
+/// Relative strength index over `period` changes, from Wilder's averages of
+/// the gains and the losses. 50 while the price stands still.
+#[derive(Debug, Clone)]
+pub struct Rsi {
+    previous: Option<f64>,
+    gains: Ema,
+    losses: Ema,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Self {
+        Self {
+            previous: None,
+            gains: Ema::wilder(period),
+            losses: Ema::wilder(period),
+        }
+    }
+}
+
+impl Indicator for Rsi {
+    fn update(&mut self, value: f64) {
+        if let Some(previous) = self.previous {
+            let change = value - previous;
+            self.gains.update(change.max(0.0));
+            self.losses.update((-change).max(0.0));
+        }
+        self.previous = Some(value);
+    }
+
+    fn value(&self) -> Option<f64> {
+        let (gains, losses) = (self.gains.value()?, self.losses.value()?);
+        // neutral if the price has not moved at all
+        match (gains == 0.0, losses == 0.0) {
+            (true, true) => Some(50.0),
+            (false, true) => Some(100.0),
+            _ => Some(100.0 - 100.0 / (1.0 + gains / losses)),
+        }
+    }
+}
+
+/// Average true range over `period` bars, with Wilder's smoothing. The true
+/// range of the first bar is its high minus its low.
+#[derive(Debug, Clone)]
+pub struct Atr {
+    previous_close: Option<f64>,
+    ranges: Ema,
+}
+
+impl Atr {
+    pub fn new(period: usize) -> Self {
+        Self {
+            previous_close: None,
+            ranges: Ema::wilder(period),
+        }
+    }
+}
+
+impl Indicator<&Quote> for Atr {
+    fn update(&mut self, q: &Quote) {
+        let range = match self.previous_close {
+            Some(close) => (q.high - q.low)
+                .max((q.high - close).abs())
+                .max((q.low - close).abs()),
+            None => q.high - q.low,
+        };
+        self.ranges.update(range);
+        self.previous_close = Some(q.close);
+    }
+
+    fn value(&self) -> Option<f64> {
+        self.ranges.value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closes() -> Vec<f64> {
+        (0..100)
+            .map(|i| 100.0 + (i as f64 * 0.7).sin() * 10.0 + i as f64 * 0.1)
+            .collect()
+    }
+
+    fn values<I: Indicator>(mut indicator: I, values: &[f64]) -> Vec<Option<f64>> {
+        values
+            .iter()
+            .map(|&v| {
+                indicator.update(v);
+                indicator.value()
+            })
+            .collect()
+    }
+
+    fn assert_close(incremental: Vec<Option<f64>>, full: Vec<Option<f64>>) {
+        assert_eq!(incremental.len(), full.len());
+        for (a, b) in incremental.into_iter().zip(full) {
+            match (a, b) {
+                (Some(a), Some(b)) => assert!((a - b).abs() < 1e-9, "{} != {}", a, b),
+                (a, b) => assert_eq!(a, b),
+            }
+        }
+    }
+
+    #[test]
+    fn sma() {
+        let closes = closes();
+        let full = (1..=closes.len())
+            .map(|l| {
+                if l < 30 {
+                    return None;
+                }
+                Some(closes[l - 30..l].iter().sum::<f64>() / 30.0)
+            })
+            .collect();
+        assert_close(values(Sma::new(30), &closes), full);
+    }
+
+    // Wilder's smoothing of `values`, recomputed from scratch
+    fn wilder(values: &[f64], period: usize) -> Option<f64> {
+        if values.len() < period {
+            return None;
+        }
+        let seed = values[..period].iter().sum::<f64>() / period as f64;
+        Some(values[period..].iter().fold(seed, |avg, v| {
+            (avg * (period - 1) as f64 + v) / period as f64
+        }))
+    }
+
+    #[test]
+    fn ema() {
+        let closes = closes();
+        let full = |period: usize, alpha: f64| -> Vec<Option<f64>> {
+            (1..=closes.len())
+                .map(|l| {
+                    if l < period {
+                        return None;
+                    }
+                    let seed = closes[..period].iter().sum::<f64>() / period as f64;
+                    Some(
+                        closes[period..l]
+                            .iter()
+                            .fold(seed, |ema, close| alpha * close + (1.0 - alpha) * ema),
+                    )
+                })
+                .collect()
+        };
+        assert_close(values(Ema::new(10), &closes), full(10, 2.0 / 11.0));
+        let wilders = (1..=closes.len())
+            .map(|l| wilder(&closes[..l], 14))
+            .collect();
+        assert_close(values(Ema::wilder(14), &closes), wilders);
+    }
+
+    #[test]
+    fn rsi() {
+        let closes = closes();
+        let full = (1..=closes.len())
+            .map(|l| {
+                let changes: Vec<f64> = closes[..l].windows(2).map(|w| w[1] - w[0]).collect();
+                let gains: Vec<f64> = changes.iter().map(|c| c.max(0.0)).collect();
+                let losses: Vec<f64> = changes.iter().map(|c| (-c).max(0.0)).collect();
+                let (gains, losses) = (wilder(&gains, 14)?, wilder(&losses, 14)?);
+                Some(100.0 - 100.0 / (1.0 + gains / losses))
+            })
+            .collect();
+        assert_close(values(Rsi::new(14), &closes), full);
+
+        // only gains
+        let rising: Vec<f64> = (0..20).map(|i| i as f64).collect();
+        assert_eq!(values(Rsi::new(14), &rising)[19], Some(100.0));
+    }
+
+    #[test]
+    fn atr() {
+        let bars: Vec<Quote> = closes()
+            .into_iter()
+            .enumerate()
+            .map(|(i, close)| Quote {
+                timestamp: i as u64,
+                open: close,
+                high: close + 1.0 + (i % 3) as f64,
+                low: close - 1.0 - (i % 4) as f64,
+                volume: 100,
+                close,
+                adjclose: close,
+            })
+            .collect();
+        let full = (1..=bars.len())
+            .map(|l| {
+                let ranges: Vec<f64> = bars[..l]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, q)| match i {
+                        0 => q.high - q.low,
+                        _ => {
+                            let close = bars[i - 1].close;
+                            (q.high - q.low)
+                                .max((q.high - close).abs())
+                                .max((q.low - close).abs())
+                        }
+                    })
+                    .collect();
+                wilder(&ranges, 14)
+            })
+            .collect();
+        let mut atr = Atr::new(14);
+        let incremental = bars
+            .iter()
+            .map(|q| {
+                atr.update(q);
+                atr.value()
+            })
+            .collect();
+        assert_close(incremental, full);
+    }
+}
@@ -1,16 +1,58 @@
 use actix::prelude::*;
+use std::sync::Arc;
+
 use tokio::sync::mpsc;
 
+pub mod actions;
+pub use actions::*;
+pub mod api;
+pub use api::*;
+pub mod breaker;
+pub use breaker::*;
+pub mod calendar;
+pub use calendar::*;
+pub mod config;
+pub use config::*;
+pub mod dashboard;
+pub use dashboard::*;
+pub mod dates;
+pub use dates::*;
+pub mod dns;
+pub use dns::*;
+pub mod error;
+pub use error::*;
+pub mod export;
+pub use export::*;
+pub mod indicators;
+pub use indicators::*;
+pub mod logging;
+pub use logging::*;
 pub mod messages;
 pub use messages::*;
+pub mod metrics;
+pub use metrics::*;
 pub mod print;
 pub use print::*;
+pub mod quotes;
+pub use quotes::*;
+pub mod repair;
+pub use repair::*;
+pub mod resample;
+pub use resample::*;
 pub mod yfetch;
 pub use yfetch::*;
 pub mod transform;
 pub use transform::*;
+pub mod source;
+pub use source::*;
+pub mod symbols;
+pub use symbols::*;
+pub mod store;
+pub use store::*;
 pub mod ticker;
 pub use ticker::*;
+pub mod ws;
+pub use ws::Hub;
 
 #[inline]
 pub fn subscribe<A, M, C>(addr: Addr<A>, mut rx: mpsc::Receiver<M>)
@@ -25,3 +67,96 @@ where
         }
     });
 }
+
+/// Like `subscribe`, but reports the number of messages still waiting in `rx`
+/// to `depth` whenever the metrics are rendered.
+#[inline]
+pub fn subscribe_metered<A, M, C>(addr: Addr<A>, rx: mpsc::Receiver<M>, depth: QueueDepth)
+where
+    M: Message<Result = ()> + Send + 'static,
+    A: Actor<Context = C> + Handler<M>,
+    C: actix::dev::ToEnvelope<A, M>,
+{
+    // the lock is only held while waiting for a message, when the channel is
+    // empty anyway, so a busy lock reads as no backlog
+    let rx = Arc::new(tokio::sync::Mutex::new(rx));
+    let probe = Arc::downgrade(&rx);
+    depth.watch(move || match probe.upgrade() {
+        Some(rx) => rx.try_lock().map_or(0, |rx| rx.len()),
+        None => 0,
+    });
+    actix::spawn(async move {
+        loop {
+            let msg = rx.lock().await.recv().await;
+            match msg {
+                Some(msg) => addr.send(msg).await.unwrap(),
+                None => break,
+            }
+        }
+    });
+}
+
+/// Duplicates each message on `rx` onto two new channels, so that the output of
+/// a single producer can be subscribed to by two actors.
+#[inline]
+pub fn tee<M>(mut rx: mpsc::Receiver<M>, bufsize: usize) -> (mpsc::Receiver<M>, mpsc::Receiver<M>)
+where
+    M: Clone + Send + 'static,
+{
+    let (tx_a, rx_a) = mpsc::channel(bufsize);
+    let (tx_b, rx_b) = mpsc::channel(bufsize);
+    actix::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            // a dropped receiver should not starve the other one
+            let _ = tx_a.send(msg.clone()).await;
+            let _ = tx_b.send(msg).await;
+        }
+    });
+    (rx_a, rx_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct Ping;
+
+    struct Stalled;
+
+    impl Actor for Stalled {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Ping> for Stalled {
+        type Result = AtomicResponse<Self, ()>;
+
+        fn handle(&mut self, _: Ping, _: &mut Context<Self>) -> Self::Result {
+            AtomicResponse::new(Box::pin(
+                actix::clock::sleep(Duration::from_secs(600)).into_actor(self),
+            ))
+        }
+    }
+
+    #[actix_rt::test]
+    async fn stalled_consumer() {
+        let metrics = Metrics::new().unwrap();
+        let (tx, rx) = mpsc::channel(8);
+        subscribe_metered(Stalled.start(), rx, metrics.queue_depth("stalled"));
+        for _ in 0..5 {
+            tx.send(Ping).await.unwrap();
+        }
+        actix::clock::sleep(Duration::from_millis(50)).await;
+
+        // one message is stuck in the actor, the rest have not been taken out
+        let text = metrics.render();
+        assert!(
+            text.lines()
+                .any(|l| l == "stock_tracker_queue_depth{queue=\"stalled\"} 4"),
+            "{}",
+            text
+        );
+    }
+}
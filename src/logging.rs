@@ -0,0 +1,57 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("expected `human` or `json`, got `{}`", s)),
+        }
+    }
+}
+
+/// Installs the global subscriber, which logs to stderr. `filter` has the
+/// syntax of `RUST_LOG`, e.g. `warn,rust_stock_tracker_lib=debug`.
+///
+/// Spans are logged when they close, together with their busy and idle time:
+/// each tick at `info`, each fetch and transformation within it at `debug`.
+pub fn init_logging(
+    format: LogFormat,
+    filter: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_new(filter)?)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Human => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    }
+}
+
+/// Logs `e` with its symbol, stage and tick as separate fields.
+pub fn log_error(e: &Error) {
+    tracing::error!(
+        symbol = e.symbol.as_deref().unwrap_or_default(),
+        stage = %e.stage,
+        kind = %e.kind.name(),
+        attempt = e.attempt,
+        tick = e.tick.unwrap_or_default(),
+        "{}",
+        e.kind
+    );
+}
@@ -1,41 +1,181 @@
+use std::fmt;
+
 use actix::prelude::*;
 use chrono::prelude::*;
+use chrono_tz::Tz;
+use serde::Serialize;
 use yahoo_finance_api as yahoo;
 
-#[derive(Debug, Message)]
+#[derive(Debug, Clone, Message, Serialize)]
 #[rtype(result = "()")]
 pub struct StockInfo {
     pub symbol: String,
     pub from: DateTime<Utc>,
+    /// Timestamp of the latest bar, the one `price` is the close of.
+    pub timestamp: DateTime<Utc>,
     pub open: f64,
     pub high: f64,
     pub low: f64,
     pub close: f64,
+    pub price: f64,
     pub sma30: Option<f64>,
+    /// Whether the bars `sma30` was computed from miss trading days, see
+    /// `Transformer::with_calendar`.
+    pub sma30_gapped: bool,
+    pub ema20: Option<f64>,
+    /// Relative strength index, with Wilder's smoothing.
+    pub rsi14: Option<f64>,
+    /// Average true range, with Wilder's smoothing.
+    pub atr14: Option<f64>,
+    pub instrument: Option<Instrument>,
+    /// Tick of the fetch this was computed from, for correlating logs.
+    #[serde(skip)]
+    pub tick: u64,
 }
 
 impl StockInfo {
+    /// The start of the period in UTC, and last the time of the latest bar in
+    /// the exchange's local time, if known.
     pub fn fmt_csv(&self) -> String {
         format!(
-            "{},{},{:.2},{:.2},{:.2},{:.2},{}",
+            "{},{},{:.2},{:.2},{:.2},{:.2},{},{},{},{},{}",
             self.from.to_rfc3339(),
             self.symbol,
             self.open,
             self.close,
             self.low,
             self.high,
-            self.sma30
-                .map_or("".to_string(), |sma30| format!("{:.2}", sma30))
+            self.fmt_sma30(),
+            fmt_indicator(self.ema20),
+            fmt_indicator(self.rsi14),
+            fmt_indicator(self.atr14),
+            match self.instrument.as_ref() {
+                Some(instrument) => instrument.local_time(self.timestamp).to_rfc3339(),
+                None => self.timestamp.to_rfc3339(),
+            }
         )
     }
+
+    /// `sma30` with two decimals, marked with `*` if its window has gaps.
+    pub fn fmt_sma30(&self) -> String {
+        match self.sma30 {
+            Some(sma30) if self.sma30_gapped => format!("{:.2}*", sma30),
+            Some(sma30) => format!("{:.2}", sma30),
+            None => "".to_string(),
+        }
+    }
 }
 
-#[derive(Debug, Message)]
+fn fmt_indicator(value: Option<f64>) -> String {
+    value.map_or_else(String::new, |value| format!("{:.2}", value))
+}
+
+#[derive(Debug, Clone, Message)]
 #[rtype(result = "()")]
 pub struct StockHistory {
     pub symbol: String,
     pub quotes: Vec<yahoo::Quote>,
+    /// Timestamps of bars the source returned without a close price, and left
+    /// out of `quotes`.
+    pub missing: Vec<u64>,
+    /// Metadata of the response, `None` if the history was not fetched.
+    pub instrument: Option<Instrument>,
+    /// Splits and dividends since `from`, ordered by date.
+    pub splits: Vec<yahoo::Split>,
+    pub dividends: Vec<yahoo::Dividend>,
     pub from: DateTime<Utc>,
+    /// Counts the fetches of the `Fetcher`, starting at 1. Histories that were
+    /// not fetched, e.g. read from the store, have tick 0.
+    pub tick: u64,
+}
+
+/// What Yahoo tells about a symbol besides its quotes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Instrument {
+    pub currency: String,
+    pub exchange: String,
+    /// IANA name of the exchange's timezone, e.g. `America/New_York`.
+    pub timezone: String,
+    /// Offset of the exchange's timezone from UTC in seconds, at the time of
+    /// the fetch.
+    pub utc_offset: i32,
+    pub market_price: f64,
+    /// Regular trading hours of the current or next trading day.
+    pub regular_start: DateTime<Utc>,
+    pub regular_end: DateTime<Utc>,
+}
+
+impl Instrument {
+    /// `at` in the exchange's local time. Falls back to the offset at the
+    /// time of the fetch for timezones chrono-tz does not know.
+    pub fn local_time(&self, at: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self.timezone.parse::<Tz>() {
+            Ok(tz) => {
+                let local = at.with_timezone(&tz);
+                local.with_timezone(&local.offset().fix())
+            }
+            Err(_) => at.with_timezone(&FixedOffset::east(self.utc_offset)),
+        }
+    }
+}
+
+impl From<&yahoo::YMetaData> for Instrument {
+    fn from(meta: &yahoo::YMetaData) -> Self {
+        let regular = &meta.current_trading_period.regular;
+        Self {
+            currency: meta.currency.clone(),
+            exchange: meta.exchange_name.clone(),
+            timezone: meta.exchange_timezone_name.clone(),
+            utc_offset: meta.gmtoffset,
+            market_price: meta.regular_market_price,
+            regular_start: Utc.timestamp(regular.start as i64, 0),
+            regular_end: Utc.timestamp(regular.end as i64, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    Split(yahoo::Split),
+    Dividend(yahoo::Dividend),
+}
+
+impl Action {
+    /// Date of the split, or the ex-dividend date.
+    pub fn date(&self) -> DateTime<Utc> {
+        let timestamp = match self {
+            Action::Split(split) => split.date,
+            Action::Dividend(dividend) => dividend.date,
+        };
+        Utc.timestamp(timestamp as i64, 0)
+    }
+}
+
+/// A split or dividend that has not been seen before, see `ActionTracker`.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct CorporateAction {
+    pub symbol: String,
+    pub action: Action,
+    pub tick: u64,
+}
+
+impl fmt::Display for CorporateAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let date = self.action.date().format("%Y-%m-%d");
+        match &self.action {
+            Action::Split(split) => write!(
+                f,
+                "{} split {}:{} on {}",
+                self.symbol, split.numerator, split.denominator, date
+            ),
+            Action::Dividend(dividend) => write!(
+                f,
+                "{} dividend of {:.4} on {}",
+                self.symbol, dividend.amount, date
+            ),
+        }
+    }
 }
 
 // signals the fetcher to fetch, starting at DateTime
@@ -43,6 +183,102 @@ pub struct StockHistory {
 #[rtype(result = "()")]
 pub struct StartFetch;
 
+// asks the fetcher how many symbols are still being fetched
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct InFlight;
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct StartTicking;
+
+// signals the exporter to write everything accumulated so far to disk
+#[derive(Message)]
+#[rtype(result = "parquet::errors::Result<()>")]
+pub struct Flush;
+
+#[derive(Message)]
+#[rtype(result = "Vec<String>")]
+pub struct GetSymbols;
+
+#[derive(Message)]
+#[rtype(result = "Option<StockInfo>")]
+pub struct GetQuote(pub String);
+
+// quotes of the latest history for `symbol`, restricted to `from..=to`,
+// with the instrument to localize their timestamps
+#[derive(Message)]
+#[rtype(result = "Option<(Vec<yahoo::Quote>, Option<Instrument>)>")]
+pub struct GetHistory {
+    pub symbol: String,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_time() {
+        let mut instrument = Instrument {
+            currency: "USD".to_string(),
+            exchange: "NMS".to_string(),
+            timezone: "America/New_York".to_string(),
+            utc_offset: -14400,
+            market_price: 1.0,
+            regular_start: Utc.timestamp(0, 0),
+            regular_end: Utc.timestamp(0, 0),
+        };
+        let summer = Utc.ymd(2021, 7, 1).and_hms(13, 30, 0);
+        let winter = Utc.ymd(2021, 12, 1).and_hms(14, 30, 0);
+        assert_eq!(
+            instrument.local_time(summer).to_rfc3339(),
+            "2021-07-01T09:30:00-04:00"
+        );
+        assert_eq!(
+            instrument.local_time(winter).to_rfc3339(),
+            "2021-12-01T09:30:00-05:00"
+        );
+
+        instrument.timezone = "Nowhere/Special".to_string();
+        assert_eq!(
+            instrument.local_time(winter).to_rfc3339(),
+            "2021-12-01T10:30:00-04:00"
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let info = StockInfo {
+            symbol: "AAPL".to_string(),
+            from: Utc.ymd(2021, 9, 1).and_hms(0, 0, 0),
+            timestamp: Utc.ymd(2021, 9, 3).and_hms(13, 30, 0),
+            open: 150.0,
+            high: 155.0,
+            low: 148.0,
+            close: 2.5,
+            price: 154.0,
+            sma30: Some(151.0),
+            sma30_gapped: false,
+            ema20: Some(152.0),
+            rsi14: None,
+            atr14: Some(3.25),
+            instrument: Some(Instrument {
+                currency: "USD".to_string(),
+                exchange: "NMS".to_string(),
+                timezone: "America/New_York".to_string(),
+                utc_offset: -14400,
+                market_price: 154.0,
+                regular_start: Utc.timestamp(0, 0),
+                regular_end: Utc.timestamp(0, 0),
+            }),
+            tick: 1,
+        };
+        // the start date as given, the latest bar as the exchange saw it
+        assert_eq!(
+            info.fmt_csv(),
+            "2021-09-01T00:00:00+00:00,AAPL,150.00,2.50,148.00,155.00,151.00,152.00,,3.25,2021-09-03T09:30:00-04:00"
+        );
+    }
+}
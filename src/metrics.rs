@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{error::Error, repair::RepairReport};
+use prometheus::{
+    CounterVec, Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+type DepthProbe = Box<dyn Fn() -> usize + Send + Sync>;
+
+/// The `queue_depth` gauge of one channel, see `Metrics::queue_depth`.
+#[derive(Clone)]
+pub struct QueueDepth {
+    queue: String,
+    probes: Arc<Mutex<HashMap<String, DepthProbe>>>,
+}
+
+impl QueueDepth {
+    /// Calls `probe` for the current backlog each time the metrics are
+    /// rendered, so the gauge stays current while nothing is consumed.
+    pub fn watch<F>(&self, probe: F)
+    where
+        F: Fn() -> usize + Send + Sync + 'static,
+    {
+        self.probes
+            .lock()
+            .unwrap()
+            .insert(self.queue.clone(), Box::new(probe));
+    }
+}
+
+/// Pipeline health metrics, rendered in the Prometheus text format. Cloning is
+/// cheap, all clones share the same underlying metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    tick_duration: Histogram,
+    fetch_duration: Histogram,
+    fetches: IntCounterVec,
+    errors: IntCounterVec,
+    repairs: IntCounterVec,
+    skipped_fetches: IntCounter,
+    worker_fetches: IntCounterVec,
+    worker_busy: CounterVec,
+    queue_depth: IntGaugeVec,
+    queue_probes: Arc<Mutex<HashMap<String, DepthProbe>>>,
+    update_age: GaugeVec,
+    last_update: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("stock_tracker".to_string()), None)?;
+
+        let tick_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "tick_duration_seconds",
+                "Time until all fetches of a tick have completed",
+            )
+            .buckets(vec![1.0, 2.5, 5.0, 10.0, 15.0, 20.0, 30.0, 45.0, 60.0]),
+        )?;
+        let fetch_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "fetch_duration_seconds",
+                "Duration of a single symbol fetch, excluding debounce",
+            )
+            .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+        )?;
+        let fetches = IntCounterVec::new(
+            Opts::new("fetches_total", "Symbol fetches by outcome"),
+            &["outcome"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new("errors_total", "Pipeline errors by stage and kind"),
+            &["stage", "kind"],
+        )?;
+        let repairs = IntCounterVec::new(
+            Opts::new("repairs_total", "Repaired bars by issue and policy"),
+            &["issue", "policy"],
+        )?;
+        let skipped_fetches = IntCounter::new(
+            "skipped_fetches_total",
+            "Fetches skipped because the previous fetch of the symbol was still in flight",
+        )?;
+        let worker_fetches = IntCounterVec::new(
+            Opts::new(
+                "worker_fetches_total",
+                "Fetches completed by each fetch worker",
+            ),
+            &["worker"],
+        )?;
+        let worker_busy = CounterVec::new(
+            Opts::new(
+                "worker_busy_seconds_total",
+                "Time each fetch worker spent fetching",
+            ),
+            &["worker"],
+        )?;
+        let queue_depth = IntGaugeVec::new(
+            Opts::new("queue_depth", "Messages waiting in a subscribed channel"),
+            &["queue"],
+        )?;
+        let update_age = GaugeVec::new(
+            Opts::new(
+                "last_update_age_seconds",
+                "Seconds since the last successful fetch of a symbol",
+            ),
+            &["symbol"],
+        )?;
+
+        registry.register(Box::new(tick_duration.clone()))?;
+        registry.register(Box::new(fetch_duration.clone()))?;
+        registry.register(Box::new(fetches.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(repairs.clone()))?;
+        registry.register(Box::new(skipped_fetches.clone()))?;
+        registry.register(Box::new(worker_fetches.clone()))?;
+        registry.register(Box::new(worker_busy.clone()))?;
+        registry.register(Box::new(queue_depth.clone()))?;
+        registry.register(Box::new(update_age.clone()))?;
+
+        Ok(Self {
+            registry,
+            tick_duration,
+            fetch_duration,
+            fetches,
+            errors,
+            repairs,
+            skipped_fetches,
+            worker_fetches,
+            worker_busy,
+            queue_depth,
+            queue_probes: Arc::new(Mutex::new(HashMap::new())),
+            update_age,
+            last_update: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn observe_tick(&self, elapsed: Duration) {
+        self.tick_duration.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_fetch(&self, elapsed: Duration) {
+        self.fetch_duration.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn fetch_succeeded(&self, symbol: &str) {
+        self.fetches.with_label_values(&["ok"]).inc();
+        self.last_update
+            .lock()
+            .unwrap()
+            .insert(symbol.to_string(), Instant::now());
+    }
+
+    /// Counts a failed fetch, labeled by `kind`, e.g. the error variant.
+    pub fn fetch_failed(&self, kind: &str) {
+        self.fetches.with_label_values(&[kind]).inc();
+    }
+
+    pub fn fetch_skipped(&self, n: u64) {
+        self.skipped_fetches.inc_by(n);
+    }
+
+    /// Counts a fetch of the `FetchSingle` numbered `worker`, and the time it
+    /// was busy with it.
+    pub fn observe_worker_fetch(&self, worker: usize, elapsed: Duration) {
+        let worker = worker.to_string();
+        self.worker_fetches.with_label_values(&[&worker]).inc();
+        self.worker_busy
+            .with_label_values(&[&worker])
+            .inc_by(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_error(&self, e: &Error) {
+        self.errors
+            .with_label_values(&[e.stage.as_str(), e.kind.name()])
+            .inc();
+    }
+
+    pub fn observe_repairs(&self, report: &RepairReport) {
+        for r in report.repairs.iter() {
+            self.repairs
+                .with_label_values(&[r.issue.as_str(), r.policy.as_str()])
+                .inc();
+        }
+    }
+
+    /// Gauge for the backlog of the channel called `queue`, to be passed to
+    /// `subscribe_metered`.
+    pub fn queue_depth(&self, queue: &str) -> QueueDepth {
+        QueueDepth {
+            queue: queue.to_string(),
+            probes: self.queue_probes.clone(),
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        for (symbol, at) in self.last_update.lock().unwrap().iter() {
+            self.update_age
+                .with_label_values(&[symbol])
+                .set(at.elapsed().as_secs_f64());
+        }
+        for (queue, probe) in self.queue_probes.lock().unwrap().iter() {
+            self.queue_depth
+                .with_label_values(&[queue])
+                .set(probe() as i64);
+        }
+
+        let mut buf = vec![];
+        // encoding into a `Vec` can only fail for malformed metric families,
+        // which we never register
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new().unwrap();
+        metrics.fetch_succeeded("AAPL");
+        metrics.fetch_succeeded("MSFT");
+        metrics.fetch_failed("Timeout");
+        metrics.fetch_skipped(2);
+        metrics.observe_fetch(Duration::from_millis(300));
+        metrics.queue_depth("fetch").watch(|| 3);
+
+        let text = metrics.render();
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            "# HELP stock_tracker_fetches_total Symbol fetches by outcome",
+            "# TYPE stock_tracker_fetches_total counter",
+            "stock_tracker_fetches_total{outcome=\"ok\"} 2",
+            "stock_tracker_fetches_total{outcome=\"Timeout\"} 1",
+            "stock_tracker_skipped_fetches_total 2",
+            "stock_tracker_queue_depth{queue=\"fetch\"} 3",
+            "# TYPE stock_tracker_fetch_duration_seconds histogram",
+            "stock_tracker_fetch_duration_seconds_bucket{le=\"0.25\"} 0",
+            "stock_tracker_fetch_duration_seconds_bucket{le=\"0.5\"} 1",
+            "stock_tracker_fetch_duration_seconds_bucket{le=\"+Inf\"} 1",
+            "stock_tracker_fetch_duration_seconds_count 1",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "missing `{}` in\n{}",
+                expected,
+                text
+            );
+        }
+        // the age is refreshed on every render
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("stock_tracker_last_update_age_seconds{symbol=\"AAPL\"} ")));
+    }
+}
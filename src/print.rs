@@ -1,16 +1,162 @@
-use crate::messages::*;
+use std::collections::HashMap;
+
 use actix::prelude::*;
+use tokio::time;
+use tracing::trace;
 
-pub struct Printer;
+use crate::messages::*;
+
+/// Prints each `StockInfo` as a CSV row. With `changes_only`, rows are only
+/// printed if they differ from the last printed row for the same symbol.
+#[derive(Default)]
+pub struct Printer {
+    filter: Option<ChangeFilter>,
+    snapshot_every: Option<time::Duration>,
+    latest: HashMap<String, StockInfo>,
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Suppresses rows whose values all lie within `tolerance` of the last
+    /// printed row for the symbol. If `snapshot_every` is set, the latest rows
+    /// for all symbols are printed at that interval regardless.
+    pub fn changes_only(tolerance: f64, snapshot_every: Option<time::Duration>) -> Self {
+        Self {
+            filter: Some(ChangeFilter::new(tolerance)),
+            snapshot_every,
+            latest: HashMap::new(),
+        }
+    }
+
+    fn print_snapshot(&mut self) {
+        let mut symbols: Vec<&String> = self.latest.keys().collect();
+        symbols.sort();
+        for symbol in symbols {
+            let info = &self.latest[symbol];
+            println!("{}", info.fmt_csv());
+            if let Some(filter) = self.filter.as_mut() {
+                filter.remember(info);
+            }
+        }
+    }
+}
 
 impl Actor for Printer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        if let Some(every) = self.snapshot_every {
+            ctx.run_interval(every, |printer, _| printer.print_snapshot());
+        }
+    }
 }
 
 impl Handler<StockInfo> for Printer {
     type Result = ();
 
     fn handle(&mut self, info: StockInfo, _: &mut Context<Self>) {
-        println!("{}", info.fmt_csv());
+        trace!(sink = "printer", tick = info.tick, symbol = %info.symbol, "received");
+        match self.filter.as_mut() {
+            None => println!("{}", info.fmt_csv()),
+            Some(filter) => {
+                if filter.changed(&info) {
+                    println!("{}", info.fmt_csv());
+                    filter.remember(&info);
+                }
+                self.latest.insert(info.symbol.clone(), info);
+            }
+        }
+    }
+}
+
+/// Remembers the last emitted `StockInfo` per symbol to tell whether a new one
+/// carries any news.
+pub struct ChangeFilter {
+    tolerance: f64,
+    emitted: HashMap<String, StockInfo>,
+}
+
+impl ChangeFilter {
+    pub fn new(tolerance: f64) -> Self {
+        Self {
+            tolerance,
+            emitted: HashMap::new(),
+        }
+    }
+
+    /// Whether any value of `info` differs by more than the tolerance from the
+    /// last emitted one for its symbol. Always true for unseen symbols.
+    pub fn changed(&self, info: &StockInfo) -> bool {
+        let last = match self.emitted.get(&info.symbol) {
+            None => return true,
+            Some(last) => last,
+        };
+        let differs = |a: f64, b: f64| (a - b).abs() > self.tolerance;
+
+        last.from != info.from
+            || differs(last.open, info.open)
+            || differs(last.high, info.high)
+            || differs(last.low, info.low)
+            || differs(last.close, info.close)
+            || differs(last.price, info.price)
+            || match (last.sma30, info.sma30) {
+                (Some(a), Some(b)) => differs(a, b),
+                (None, None) => false,
+                _ => true,
+            }
+    }
+
+    pub fn remember(&mut self, info: &StockInfo) {
+        self.emitted.insert(info.symbol.clone(), info.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    fn info(symbol: &str, close: f64, sma30: Option<f64>) -> StockInfo {
+        StockInfo {
+            symbol: symbol.to_string(),
+            from: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            timestamp: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            open: 1.0,
+            high: 2.0,
+            low: 0.5,
+            close,
+            price: 1.0,
+            sma30,
+            sma30_gapped: false,
+            ema20: None,
+            rsi14: None,
+            atr14: None,
+            instrument: None,
+            tick: 0,
+        }
+    }
+
+    #[test]
+    fn change_filter() {
+        let mut filter = ChangeFilter::new(0.01);
+
+        // unseen symbols always count as changed
+        assert!(filter.changed(&info("AAPL", 1.0, None)));
+        filter.remember(&info("AAPL", 1.0, None));
+        assert!(filter.changed(&info("MSFT", 1.0, None)));
+
+        // changes within the tolerance are suppressed
+        assert!(!filter.changed(&info("AAPL", 1.0, None)));
+        assert!(!filter.changed(&info("AAPL", 1.005, None)));
+        assert!(filter.changed(&info("AAPL", 1.02, None)));
+
+        // an SMA appearing is a change
+        assert!(filter.changed(&info("AAPL", 1.0, Some(1.0))));
+        filter.remember(&info("AAPL", 1.0, Some(1.0)));
+        assert!(!filter.changed(&info("AAPL", 1.0, Some(1.001))));
+        assert!(filter.changed(&info("AAPL", 1.0, None)));
     }
 }
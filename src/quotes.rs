@@ -0,0 +1,407 @@
+use std::{error::Error as StdError, fmt, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::prelude::*;
+use yahoo::{YahooConnector, YahooError};
+use yahoo_finance_api as yahoo;
+
+use crate::{
+    dns::*,
+    error::*,
+    messages::Instrument,
+    source::{SymbolNormalizer, YahooSymbols},
+};
+
+/// Yahoo's chart endpoint, the default base URL of `HttpSource`.
+pub const YAHOO_CHART_URL: &str = "https://query1.finance.yahoo.com/v8/finance/chart";
+
+/// Bars, corporate actions and instrument metadata of a single symbol, as
+/// returned by a `QuoteSource`.
+#[derive(Debug, Clone)]
+pub struct Chart {
+    pub quotes: Vec<yahoo::Quote>,
+    /// Timestamps of bars without a close price, which `quotes` leaves out.
+    pub missing: Vec<u64>,
+    pub instrument: Option<Instrument>,
+    pub splits: Vec<yahoo::Split>,
+    pub dividends: Vec<yahoo::Dividend>,
+}
+
+impl Chart {
+    pub fn from_response(res: &yahoo::YResponse) -> Result<Self, YahooError> {
+        // `YResponse` indexes the first result without checking
+        let result = match res.chart.result.first() {
+            Some(result) => result,
+            None => return Err(YahooError::EmptyDataSet),
+        };
+        let quotes = res.quotes()?;
+        // both are ordered the way Yahoo sent them, usually by time
+        let mut returned = quotes.iter().map(|q| q.timestamp).peekable();
+        let mut missing = vec![];
+        for &timestamp in result.timestamp.iter() {
+            if returned.peek() == Some(&timestamp) {
+                returned.next();
+            } else {
+                missing.push(timestamp);
+            }
+        }
+        Ok(Self {
+            quotes,
+            missing,
+            instrument: Some(Instrument::from(&result.meta)),
+            splits: res.splits()?,
+            dividends: res.dividends()?,
+        })
+    }
+}
+
+/// Where the `Fetcher` gets its quotes from.
+#[async_trait]
+pub trait QuoteSource: Send + Sync {
+    /// Bars of `interval` (e.g. `1d`) for `symbol` between `from` and `to`.
+    /// Errors carry the symbol and the stage they occurred in.
+    ///
+    /// Prices and volumes must already be adjusted for splits, as Yahoo's
+    /// are. Nothing downstream adjusts them, so the bars of a source that
+    /// returns them as traded would jump at each split, and so would the SMA.
+    async fn history(
+        &self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        interval: &str,
+    ) -> Result<Chart, Error>;
+
+    /// The spelling of `symbol` this source expects. Both our clients talk to
+    /// Yahoo, see `YahooSymbols`.
+    fn normalize(&self, symbol: &str) -> String {
+        YahooSymbols.normalize(symbol)
+    }
+}
+
+/// The client of `yahoo_finance_api`, which opens a new connection for every
+/// request.
+#[derive(Default)]
+pub struct YahooSource {
+    connector: YahooConnector,
+}
+
+impl YahooSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl QuoteSource for YahooSource {
+    async fn history(
+        &self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        interval: &str,
+    ) -> Result<Chart, Error> {
+        let error = |stage, e| Error::new(Some(symbol.to_string()), stage, ErrorKind::Yahoo(e));
+        let res = self
+            .connector
+            .get_quote_history_interval(symbol, from, to, interval)
+            .await
+            .map_err(|e| error(Stage::Fetch, e))?;
+        Chart::from_response(&res).map_err(|e| error(Stage::Parse, e))
+    }
+}
+
+/// Native client for Yahoo's chart API. All requests share one connection
+/// pool, so that connections are kept alive between ticks instead of paying
+/// for DNS, TCP and TLS on every request. Hosts that offer HTTP/2 during the
+/// TLS handshake get all requests multiplexed over a single connection.
+#[derive(Clone)]
+pub struct HttpSource {
+    client: reqwest::Client,
+    base_url: String,
+    dns: DnsCache,
+    root_certificates: Vec<reqwest::Certificate>,
+}
+
+impl HttpSource {
+    pub fn new() -> reqwest::Result<Self> {
+        let dns = DnsCache::new(DNS_TTL);
+        Ok(Self {
+            client: build_client(&dns, &[])?,
+            base_url: YAHOO_CHART_URL.to_string(),
+            dns,
+            root_certificates: vec![],
+        })
+    }
+
+    /// Also trusts the CA certificate in `pem`, e.g. that of a local stand-in
+    /// with a self-signed certificate.
+    pub fn with_root_certificate(mut self, pem: &[u8]) -> reqwest::Result<Self> {
+        self.root_certificates
+            .push(reqwest::Certificate::from_pem(pem)?);
+        self.client = build_client(&self.dns, &self.root_certificates)?;
+        Ok(self)
+    }
+
+    /// Sends requests to `base_url` instead of Yahoo, e.g. to a local
+    /// stand-in. Symbols are appended as the last path segment.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Connections and DNS lookups so far, shared by all clones.
+    pub fn stats(&self) -> ConnectionStats {
+        self.dns.stats()
+    }
+
+    async fn request(
+        &self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        interval: &str,
+    ) -> Result<Chart, HttpError> {
+        let response = self
+            .client
+            .get(format!("{}/{}", self.base_url, symbol))
+            .query(&[
+                ("symbol", symbol),
+                ("period1", &from.timestamp().to_string()),
+                ("period2", &to.timestamp().to_string()),
+                ("interval", interval),
+                ("events", "div|split"),
+            ])
+            .send()
+            .await
+            .map_err(HttpError::from_reqwest)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(HttpError::Status(status.as_u16()));
+        }
+        let body = response.bytes().await.map_err(HttpError::from_reqwest)?;
+        parse(&body)
+    }
+}
+
+fn build_client(
+    dns: &DnsCache,
+    root_certificates: &[reqwest::Certificate],
+) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(concat!("rust-stock-tracker/", env!("CARGO_PKG_VERSION")))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .pool_max_idle_per_host(64)
+        .tcp_keepalive(Duration::from_secs(60))
+        .http2_adaptive_window(true)
+        .dns_resolver(Arc::new(dns.clone()));
+    for certificate in root_certificates {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    builder.build()
+}
+
+#[async_trait]
+impl QuoteSource for HttpSource {
+    async fn history(
+        &self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        interval: &str,
+    ) -> Result<Chart, Error> {
+        self.request(symbol, from, to, interval)
+            .await
+            .map_err(|e| Error::new(Some(symbol.to_string()), e.stage(), ErrorKind::Http(e)))
+    }
+}
+
+fn parse(body: &[u8]) -> Result<Chart, HttpError> {
+    let res: yahoo::YResponse =
+        serde_json::from_slice(body).map_err(|e| HttpError::Json(e.to_string()))?;
+    Chart::from_response(&res).map_err(|e| match e {
+        YahooError::EmptyDataSet => HttpError::Empty,
+        YahooError::DataInconsistency => {
+            HttpError::Inconsistent("quote lengths differ from the number of timestamps".into())
+        }
+        e => HttpError::Json(e.to_string()),
+    })
+}
+
+/// What went wrong with a request of `HttpSource`.
+#[derive(Debug)]
+pub enum HttpError {
+    /// The host name could not be resolved.
+    Dns(String),
+    /// The TLS handshake failed, e.g. because of an invalid certificate.
+    Tls(String),
+    /// No connection could be established, or it broke down.
+    Connect(String),
+    /// The server answered with a status other than 2xx, e.g. 404 for
+    /// unknown symbols or 429 when rate limited.
+    Status(u16),
+    /// The body is not JSON, or not the expected JSON.
+    Json(String),
+    /// The response contains no quotes.
+    Empty,
+    /// The response is well-formed, but its parts do not fit together.
+    Inconsistent(String),
+    /// Anything else, e.g. an invalid base URL.
+    Request(String),
+}
+
+impl HttpError {
+    /// Failures to get a response are `Stage::Fetch`, failures to make sense
+    /// of it `Stage::Parse`.
+    pub fn stage(&self) -> Stage {
+        match self {
+            HttpError::Json(_) | HttpError::Empty | HttpError::Inconsistent(_) => Stage::Parse,
+            _ => Stage::Fetch,
+        }
+    }
+
+    /// Name of the variant, e.g. for metric labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HttpError::Dns(_) => "Dns",
+            HttpError::Tls(_) => "Tls",
+            HttpError::Connect(_) => "Connect",
+            HttpError::Status(_) => "Status",
+            HttpError::Json(_) => "Json",
+            HttpError::Empty => "Empty",
+            HttpError::Inconsistent(_) => "Inconsistent",
+            HttpError::Request(_) => "Request",
+        }
+    }
+
+    fn from_reqwest(e: reqwest::Error) -> Self {
+        if let Some(status) = e.status() {
+            return HttpError::Status(status.as_u16());
+        }
+        // reqwest only tells connection errors apart from others, the cause
+        // is buried in the source chain, e.g. `error sending request for url
+        // (...): error trying to connect: dns error: failed to lookup address
+        // information: Name or service not known`
+        let mut message = e.to_string();
+        let mut source = e.source();
+        while let Some(cause) = source {
+            // some causes already include their own causes
+            let cause_message = cause.to_string();
+            if !message.contains(&cause_message) {
+                message.push_str(": ");
+                message.push_str(&cause_message);
+            }
+            source = cause.source();
+        }
+        classify(message, e.is_connect())
+    }
+}
+
+fn classify(message: String, connect: bool) -> HttpError {
+    let lower = message.to_lowercase();
+    if lower.contains("dns error") || lower.contains("failed to lookup address") {
+        HttpError::Dns(message)
+    } else if connect
+        && (lower.contains("tls") || lower.contains("certificate") || lower.contains("handshake"))
+    {
+        HttpError::Tls(message)
+    } else if connect {
+        HttpError::Connect(message)
+    } else {
+        HttpError::Request(message)
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpError::Dns(e) => write!(f, "DNS lookup failed: {}", e),
+            HttpError::Tls(e) => write!(f, "TLS handshake failed: {}", e),
+            HttpError::Connect(e) => write!(f, "connection failed: {}", e),
+            HttpError::Status(status) => write!(f, "unexpected HTTP status {}", status),
+            HttpError::Json(e) => write!(f, "invalid JSON: {}", e),
+            HttpError::Empty => write!(f, "response contains no quotes"),
+            HttpError::Inconsistent(e) => write!(f, "inconsistent data: {}", e),
+            HttpError::Request(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &str = r#"{"chart": {"result": [{
+        "meta": {
+            "currency": "USD", "symbol": "AAPL", "exchangeName": "NMS",
+            "instrumentType": "EQUITY", "firstTradeDate": 345479400,
+            "regularMarketTime": 1634328002, "gmtoffset": -14400,
+            "timezone": "EDT", "exchangeTimezoneName": "America/New_York",
+            "regularMarketPrice": 144.84, "chartPreviousClose": 142.81,
+            "priceHint": 2,
+            "currentTradingPeriod": {
+                "pre": {"timezone": "EDT", "start": 1634284800, "end": 1634304600, "gmtoffset": -14400},
+                "regular": {"timezone": "EDT", "start": 1634304600, "end": 1634328000, "gmtoffset": -14400},
+                "post": {"timezone": "EDT", "start": 1634328000, "end": 1634342400, "gmtoffset": -14400}
+            },
+            "dataGranularity": "1d", "range": "", "validRanges": ["1d"]
+        },
+        "timestamp": [1634218200, 1634304600],
+        "events": {"dividends": {"1628256600": {"amount": 0.22, "date": 1628256600}}},
+        "indicators": {
+            "quote": [{
+                "open": [143.23, 143.77], "high": [143.88, 144.9],
+                "low": [141.51, 143.51], "close": [143.76, 144.84],
+                "volume": [69907100, 67885200]
+            }],
+            "adjclose": [{"adjclose": [143.76, 144.84]}]
+        }
+    }], "error": null}}"#;
+
+    #[test]
+    fn parse_response() {
+        let chart = parse(RESPONSE.as_bytes()).unwrap();
+        assert_eq!(chart.quotes.len(), 2);
+        assert_eq!(chart.quotes[1].close, 144.84);
+        assert_eq!(chart.dividends.len(), 1);
+        assert!(chart.splits.is_empty());
+        assert_eq!(chart.instrument.unwrap().currency, "USD");
+
+        let null_close = RESPONSE.replace(r#""close": [143.76"#, r#""close": [null"#);
+        let chart = parse(null_close.as_bytes()).unwrap();
+        assert_eq!(chart.quotes.len(), 1);
+        assert_eq!(chart.missing, vec![1634218200]);
+
+        let inconsistent = RESPONSE.replace("[143.76, 144.84]", "[143.76]");
+        assert!(matches!(
+            parse(inconsistent.as_bytes()),
+            Err(HttpError::Inconsistent(_))
+        ));
+        let empty = RESPONSE.replace("[1634218200, 1634304600]", "[]");
+        assert!(matches!(parse(empty.as_bytes()), Err(HttpError::Empty)));
+        assert!(matches!(parse(b"<html>"), Err(HttpError::Json(_))));
+    }
+
+    #[test]
+    fn classify_errors() {
+        let dns = "error sending request: error trying to connect: dns error: failed to lookup address information";
+        assert!(matches!(classify(dns.into(), true), HttpError::Dns(_)));
+        let tls = "error trying to connect: invalid peer certificate: UnknownIssuer";
+        assert!(matches!(classify(tls.into(), true), HttpError::Tls(_)));
+        let refused = "error trying to connect: tcp connect error: Connection refused";
+        assert!(matches!(
+            classify(refused.into(), true),
+            HttpError::Connect(_)
+        ));
+        assert!(matches!(
+            classify("builder error: relative URL without a base".into(), false),
+            HttpError::Request(_)
+        ));
+    }
+}
@@ -0,0 +1,548 @@
+use std::fmt;
+
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, debug_span};
+use yahoo_finance_api::Quote;
+
+use crate::{error::*, messages::*};
+
+/// Something wrong with a single bar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Issue {
+    /// The source knows the timestamp, but not its close price.
+    Missing,
+    /// Earlier than the bar before it.
+    OutOfOrder,
+    /// Same timestamp as the bar before it.
+    Duplicate,
+    HighBelowLow,
+    /// Open, high, low or close is zero, negative or not a number. Yahoo
+    /// reports missing values other than the close as zero.
+    NonPositive,
+    /// The close moves away from the previous close by more than the spike
+    /// threshold, and right back at the next bar.
+    Spike,
+}
+
+impl Issue {
+    /// Whether `policy` can be applied to bars with this issue.
+    pub fn allows(&self, policy: Policy) -> bool {
+        match (self, policy) {
+            (_, Policy::Drop) | (_, Policy::Error) => true,
+            (Issue::OutOfOrder, policy) => policy == Policy::Sort,
+            (Issue::Duplicate, policy) => policy == Policy::KeepLast,
+            (_, policy) => policy == Policy::ForwardFill,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Issue::Missing => "missing",
+            Issue::OutOfOrder => "out_of_order",
+            Issue::Duplicate => "duplicate",
+            Issue::HighBelowLow => "high_below_low",
+            Issue::NonPositive => "non_positive",
+            Issue::Spike => "spike",
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Issue::Missing => "missing",
+            Issue::OutOfOrder => "out of order",
+            Issue::Duplicate => "duplicate",
+            Issue::HighBelowLow => "high below low",
+            Issue::NonPositive => "non-positive",
+            Issue::Spike => "spike",
+        })
+    }
+}
+
+/// What to do about a bar with an issue. `Drop` and `Error` apply to all
+/// issues, the others only to some, see `Issue::allows`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// Leave the bar out. Of duplicates, the first one is kept.
+    Drop,
+    /// Replace the bar with a flat one at the close of the last good bar, or
+    /// drop it if there is none. Not for out-of-order bars and duplicates.
+    ForwardFill,
+    /// Sort out-of-order bars in.
+    Sort,
+    /// Keep the last of duplicates instead of the first.
+    KeepLast,
+    /// Report the whole history as `ErrorKind::Invalid` instead of passing
+    /// it on.
+    Error,
+}
+
+impl Policy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Policy::Drop => "drop",
+            Policy::ForwardFill => "forward_fill",
+            Policy::Sort => "sort",
+            Policy::KeepLast => "keep_last",
+            Policy::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepairConfig {
+    pub missing: Policy,
+    pub out_of_order: Policy,
+    pub duplicate: Policy,
+    pub high_below_low: Policy,
+    pub non_positive: Policy,
+    pub spike: Policy,
+    /// Relative change of the close that counts as a spike, e.g. 0.5 for 50%.
+    pub spike_threshold: f64,
+}
+
+impl Default for RepairConfig {
+    fn default() -> Self {
+        Self {
+            missing: Policy::Drop,
+            out_of_order: Policy::Sort,
+            duplicate: Policy::Drop,
+            high_below_low: Policy::Drop,
+            non_positive: Policy::Drop,
+            spike: Policy::Drop,
+            spike_threshold: 0.5,
+        }
+    }
+}
+
+const ISSUES: [Issue; 6] = [
+    Issue::Missing,
+    Issue::OutOfOrder,
+    Issue::Duplicate,
+    Issue::HighBelowLow,
+    Issue::NonPositive,
+    Issue::Spike,
+];
+
+impl RepairConfig {
+    /// Fails with the first issue whose policy does not apply to it.
+    pub fn check(&self) -> Result<(), (Issue, Policy)> {
+        match ISSUES
+            .iter()
+            .find(|issue| !issue.allows(self.policy(**issue)))
+        {
+            Some(&issue) => Err((issue, self.policy(issue))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn policy(&self, issue: Issue) -> Policy {
+        match issue {
+            Issue::Missing => self.missing,
+            Issue::OutOfOrder => self.out_of_order,
+            Issue::Duplicate => self.duplicate,
+            Issue::HighBelowLow => self.high_below_low,
+            Issue::NonPositive => self.non_positive,
+            Issue::Spike => self.spike,
+        }
+    }
+}
+
+/// A bar that was dropped, filled or moved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repair {
+    pub issue: Issue,
+    pub timestamp: u64,
+    pub policy: Policy,
+}
+
+/// What the `Repairer` did to a history, sent for every history it changed.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct RepairReport {
+    pub symbol: String,
+    pub tick: u64,
+    pub repairs: Vec<Repair>,
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // (issue, policy, count) in order of appearance
+        let mut counts: Vec<(Issue, Policy, usize)> = vec![];
+        for r in self.repairs.iter() {
+            match counts
+                .iter_mut()
+                .find(|(issue, policy, _)| *issue == r.issue && *policy == r.policy)
+            {
+                Some((_, _, count)) => *count += 1,
+                None => counts.push((r.issue, r.policy, 1)),
+            }
+        }
+        write!(f, "{} repaired:", self.symbol)?;
+        for (i, (issue, policy, count)) in counts.into_iter().enumerate() {
+            let done = match policy {
+                Policy::Sort => "sorted",
+                Policy::KeepLast => "replaced",
+                Policy::ForwardFill => "filled",
+                _ => "dropped",
+            };
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{} {} {}", sep, count, issue, done)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks fetched bars before they reach the `Transformer`, and drops or
+/// fills the broken ones according to a `RepairConfig`.
+pub struct Repairer {
+    config: RepairConfig,
+    hist_tx: mpsc::Sender<StockHistory>,
+    report_tx: mpsc::Sender<RepairReport>,
+    err_tx: Option<mpsc::Sender<Error>>,
+}
+
+impl Repairer {
+    pub fn new(
+        config: RepairConfig,
+        bufsize: usize,
+    ) -> (
+        Self,
+        mpsc::Receiver<StockHistory>,
+        mpsc::Receiver<RepairReport>,
+    ) {
+        let (hist_tx, hist_rx) = mpsc::channel(bufsize);
+        let (report_tx, report_rx) = mpsc::channel(bufsize);
+        let repairer = Self {
+            config,
+            hist_tx,
+            report_tx,
+            err_tx: None,
+        };
+        (repairer, hist_rx, report_rx)
+    }
+
+    /// Reports histories with an issue whose policy is `Policy::Error` to
+    /// `err_tx`, instead of silently dropping them.
+    pub fn with_errors(mut self, err_tx: mpsc::Sender<Error>) -> Self {
+        self.err_tx = Some(err_tx);
+        self
+    }
+
+    /// The repaired history, and what was done to it.
+    pub fn repair(&self, mut history: StockHistory) -> Result<(StockHistory, RepairReport), Error> {
+        match repair_quotes(&history.quotes, &history.missing, &self.config) {
+            Ok((quotes, repairs)) => {
+                let report = RepairReport {
+                    symbol: history.symbol.clone(),
+                    tick: history.tick,
+                    repairs,
+                };
+                history.quotes = quotes;
+                history.missing.clear();
+                Ok((history, report))
+            }
+            Err(repair) => Err(Error::new(
+                Some(history.symbol),
+                Stage::Validate,
+                ErrorKind::Invalid(repair.issue, repair.timestamp),
+            )
+            .with_tick(history.tick)),
+        }
+    }
+}
+
+impl Actor for Repairer {
+    type Context = Context<Self>;
+}
+
+impl Handler<StockHistory> for Repairer {
+    type Result = ();
+
+    fn handle(&mut self, history: StockHistory, _: &mut Context<Self>) {
+        let _span = debug_span!("repair", tick = history.tick, symbol = %history.symbol).entered();
+        match self.repair(history) {
+            Ok((history, report)) => {
+                debug!(repairs = report.repairs.len(), "repaired");
+                let hist_tx = self.hist_tx.clone();
+                let report_tx = self.report_tx.clone();
+                actix::spawn(async move {
+                    if !report.repairs.is_empty() {
+                        let _ = report_tx.send(report).await;
+                    }
+                    hist_tx.send(history).await.unwrap();
+                });
+            }
+            Err(e) => {
+                if let Some(err_tx) = self.err_tx.clone() {
+                    actix::spawn(async move {
+                        let _ = err_tx.send(e).await;
+                    });
+                }
+            }
+        }
+    }
+}
+
+// collects the repairs, and stops at the first issue whose policy is `Error`
+struct Repairs<'a> {
+    config: &'a RepairConfig,
+    list: Vec<Repair>,
+}
+
+impl Repairs<'_> {
+    fn note(&mut self, issue: Issue, timestamp: u64) -> Result<Policy, Repair> {
+        let policy = self.config.policy(issue);
+        let repair = Repair {
+            issue,
+            timestamp,
+            policy,
+        };
+        if policy == Policy::Error {
+            return Err(repair);
+        }
+        self.list.push(repair);
+        Ok(policy)
+    }
+}
+
+/// Orders `quotes` by time and applies the policy of `config` to each bar
+/// with an issue, including a placeholder for each of the `missing`
+/// timestamps. Fails with the first issue whose policy is `Policy::Error`.
+pub fn repair_quotes(
+    quotes: &[Quote],
+    missing: &[u64],
+    config: &RepairConfig,
+) -> Result<(Vec<Quote>, Vec<Repair>), Repair> {
+    let mut repairs = Repairs {
+        config,
+        list: vec![],
+    };
+
+    let mut ordered: Vec<Quote> = Vec::with_capacity(quotes.len());
+    let mut sort = false;
+    for q in quotes {
+        if ordered
+            .last()
+            .is_some_and(|last| q.timestamp < last.timestamp)
+        {
+            match repairs.note(Issue::OutOfOrder, q.timestamp)? {
+                Policy::Drop => continue,
+                _ => sort = true,
+            }
+        }
+        ordered.push(q.clone());
+    }
+    if sort {
+        // stable, so that duplicates keep their order
+        ordered.sort_by_key(|q| q.timestamp);
+    }
+
+    // `None` for missing bars
+    let mut bars: Vec<(u64, Option<Quote>)> = Vec::with_capacity(ordered.len() + missing.len());
+    for q in ordered {
+        match bars.last_mut() {
+            Some((timestamp, Some(last))) if *timestamp == q.timestamp => {
+                if repairs.note(Issue::Duplicate, q.timestamp)? == Policy::KeepLast {
+                    *last = q;
+                }
+            }
+            _ => bars.push((q.timestamp, Some(q))),
+        }
+    }
+    if !missing.is_empty() {
+        bars.extend(missing.iter().map(|&timestamp| (timestamp, None)));
+        bars.sort_by_key(|(timestamp, _)| *timestamp);
+    }
+
+    let threshold = config.spike_threshold;
+    let mut repaired: Vec<Quote> = Vec::with_capacity(bars.len());
+    let mut last_good: Option<Quote> = None;
+    for (i, (timestamp, bar)) in bars.iter().enumerate() {
+        let issue = match bar {
+            None => Some(Issue::Missing),
+            Some(q) if !positive(q) => Some(Issue::NonPositive),
+            Some(q) if q.high < q.low => Some(Issue::HighBelowLow),
+            Some(q) => {
+                let next = bars[i + 1..]
+                    .iter()
+                    .filter_map(|(_, bar)| bar.as_ref())
+                    .find(|next| positive(next) && next.high >= next.low);
+                match (last_good.as_ref(), next) {
+                    (Some(prev), Some(next))
+                        if is_spike(prev.close, q.close, next.close, threshold) =>
+                    {
+                        Some(Issue::Spike)
+                    }
+                    _ => None,
+                }
+            }
+        };
+
+        let issue = match issue {
+            Some(issue) => issue,
+            None => {
+                // bars without an issue are never missing
+                let q = bar.clone().unwrap();
+                last_good = Some(q.clone());
+                repaired.push(q);
+                continue;
+            }
+        };
+        match (repairs.note(issue, *timestamp)?, last_good.as_ref()) {
+            (Policy::ForwardFill, Some(prev)) => repaired.push(Quote {
+                timestamp: *timestamp,
+                open: prev.close,
+                high: prev.close,
+                low: prev.close,
+                close: prev.close,
+                adjclose: prev.adjclose,
+                volume: 0,
+            }),
+            // nothing to fill from
+            (Policy::ForwardFill, None) => repairs.list.last_mut().unwrap().policy = Policy::Drop,
+            _ => {}
+        }
+    }
+
+    Ok((repaired, repairs.list))
+}
+
+// `adjclose` is left out, Yahoo does not adjust intraday bars
+fn positive(q: &Quote) -> bool {
+    [q.open, q.high, q.low, q.close]
+        .iter()
+        .all(|p| p.is_finite() && *p > 0.0)
+}
+
+fn is_spike(prev: f64, close: f64, next: f64, threshold: f64) -> bool {
+    (close / prev - 1.0).abs() > threshold
+        && (close / next - 1.0).abs() > threshold
+        && (next / prev - 1.0).abs() <= threshold
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(timestamp: u64, close: f64) -> Quote {
+        Quote {
+            timestamp,
+            open: close,
+            high: close,
+            low: close,
+            volume: 100,
+            close,
+            adjclose: close,
+        }
+    }
+
+    fn closes(quotes: &[Quote]) -> Vec<(u64, f64)> {
+        quotes.iter().map(|q| (q.timestamp, q.close)).collect()
+    }
+
+    #[test]
+    fn repair() {
+        let mut inverted = quote(5, 10.0);
+        inverted.low = 11.0;
+        let mut zero_open = quote(6, 10.0);
+        zero_open.open = 0.0;
+        let quotes = vec![
+            quote(2, 10.0),
+            quote(1, 10.0),
+            quote(2, 11.0),
+            quote(4, 30.0),
+            inverted,
+            zero_open,
+            quote(7, 10.5),
+        ];
+        let config = RepairConfig::default();
+        let (repaired, repairs) = repair_quotes(&quotes, &[3], &config).unwrap();
+        assert_eq!(closes(&repaired), vec![(1, 10.0), (2, 10.0), (7, 10.5)]);
+        let issues: Vec<Issue> = repairs.iter().map(|r| r.issue).collect();
+        assert_eq!(
+            issues,
+            vec![
+                Issue::OutOfOrder,
+                Issue::Duplicate,
+                Issue::Missing,
+                Issue::Spike,
+                Issue::HighBelowLow,
+                Issue::NonPositive
+            ]
+        );
+
+        let fill = RepairConfig {
+            missing: Policy::ForwardFill,
+            duplicate: Policy::KeepLast,
+            spike: Policy::ForwardFill,
+            ..RepairConfig::default()
+        };
+        assert!(fill.check().is_ok());
+        let (repaired, _) = repair_quotes(&quotes, &[3], &fill).unwrap();
+        assert_eq!(
+            closes(&repaired),
+            vec![(1, 10.0), (2, 11.0), (3, 11.0), (4, 11.0), (7, 10.5)]
+        );
+        assert_eq!(repaired[2].volume, 0);
+
+        // nothing to fill the first bar from
+        let (repaired, repairs) = repair_quotes(&[quote(2, 10.0)], &[1], &fill).unwrap();
+        assert_eq!(closes(&repaired), vec![(2, 10.0)]);
+        assert_eq!(repairs[0].policy, Policy::Drop);
+
+        let strict = RepairConfig {
+            high_below_low: Policy::Error,
+            ..RepairConfig::default()
+        };
+        let e = repair_quotes(&quotes, &[], &strict).unwrap_err();
+        assert_eq!((e.issue, e.timestamp), (Issue::HighBelowLow, 5));
+
+        let misplaced = RepairConfig {
+            duplicate: Policy::ForwardFill,
+            ..RepairConfig::default()
+        };
+        assert_eq!(
+            misplaced.check(),
+            Err((Issue::Duplicate, Policy::ForwardFill))
+        );
+
+        // a jump that lasts is no spike
+        let jump = vec![quote(1, 10.0), quote(2, 30.0), quote(3, 31.0)];
+        assert!(repair_quotes(&jump, &[], &config).unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn report() {
+        let report = RepairReport {
+            symbol: "AAPL".to_string(),
+            tick: 1,
+            repairs: vec![
+                Repair {
+                    issue: Issue::Duplicate,
+                    timestamp: 1,
+                    policy: Policy::Drop,
+                },
+                Repair {
+                    issue: Issue::Missing,
+                    timestamp: 2,
+                    policy: Policy::ForwardFill,
+                },
+                Repair {
+                    issue: Issue::Duplicate,
+                    timestamp: 3,
+                    policy: Policy::Drop,
+                },
+            ],
+        };
+        assert_eq!(
+            report.to_string(),
+            "AAPL repaired: 2 duplicate dropped, 1 missing filled"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::{collections::HashMap, convert::TryFrom, fmt, str::FromStr};
+
+use actix::prelude::*;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, debug_span};
+use yahoo_finance_api::Quote;
+
+use crate::messages::*;
+
+/// The period of the bars `resample` aggregates into. Periods are aligned to
+/// the exchange's local calendar: weeks start on Monday, months on the 1st,
+/// and N-minute bars at the start of regular trading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Timeframe {
+    /// Needs intraday bars, daily ones stay as they are.
+    Minutes(u32),
+    Week,
+    Month,
+}
+
+impl FromStr for Timeframe {
+    type Err = String;
+
+    /// `15m`, `1h`, `1w` or `1mo`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid timeframe: {}", s);
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let n: u32 = s[..split].parse().map_err(|_| invalid())?;
+        match (n, &s[split..]) {
+            (0, _) => Err(invalid()),
+            (n, "m") => Ok(Timeframe::Minutes(n)),
+            (n, "h") => Ok(Timeframe::Minutes(n * 60)),
+            (1, "w") => Ok(Timeframe::Week),
+            (1, "mo") => Ok(Timeframe::Month),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for Timeframe {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Timeframe> for String {
+    fn from(timeframe: Timeframe) -> Self {
+        timeframe.to_string()
+    }
+}
+
+impl fmt::Display for Timeframe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Timeframe::Minutes(n) => write!(f, "{}m", n),
+            Timeframe::Week => f.write_str("1w"),
+            Timeframe::Month => f.write_str("1mo"),
+        }
+    }
+}
+
+impl Timeframe {
+    /// The bars to fetch for this timeframe: the largest intraday interval
+    /// Yahoo offers that divides N minutes, and daily bars otherwise. Yahoo
+    /// only keeps intraday bars for the last few weeks.
+    pub fn interval(&self) -> &'static str {
+        match self {
+            Timeframe::Minutes(n) => [(60, "60m"), (30, "30m"), (15, "15m"), (5, "5m"), (2, "2m")]
+                .iter()
+                .find(|(minutes, _)| n % minutes == 0)
+                .map_or("1m", |(_, interval)| interval),
+            Timeframe::Week | Timeframe::Month => "1d",
+        }
+    }
+
+    /// Identifies the period `at` falls into, given in local time. `open` is
+    /// the local time of day N-minute periods are aligned to, anew each day.
+    fn period(&self, at: NaiveDateTime, open: NaiveTime) -> (i64, i64) {
+        let day = at.date().num_days_from_ce() as i64;
+        match self {
+            Timeframe::Minutes(n) => {
+                let since_open = at - at.date().and_time(open);
+                (day, since_open.num_minutes().div_euclid(*n as i64))
+            }
+            Timeframe::Week => (day - at.weekday().num_days_from_monday() as i64, 0),
+            Timeframe::Month => (at.year() as i64, at.month0() as i64),
+        }
+    }
+}
+
+/// Aggregates ordered `quotes` into bars of `timeframe`: the open of the first
+/// bar of each period, the highest high, the lowest low, the close and
+/// adjusted close of the last bar, and the sum of the volumes. Each bar keeps
+/// the timestamp of its first quote.
+///
+/// Periods are in the exchange's local time, or in UTC if the exchange is not
+/// known, in which case N-minute bars are aligned to midnight.
+pub fn resample(
+    quotes: &[Quote],
+    timeframe: Timeframe,
+    instrument: Option<&Instrument>,
+) -> Vec<Quote> {
+    let local = |timestamp: u64| {
+        let at = Utc.timestamp(timestamp as i64, 0);
+        match instrument {
+            Some(instrument) => instrument.local_time(at).naive_local(),
+            None => at.naive_utc(),
+        }
+    };
+    let open = instrument.map_or(NaiveTime::from_hms(0, 0, 0), |instrument| {
+        instrument.local_time(instrument.regular_start).time()
+    });
+
+    let mut bars: Vec<Quote> = vec![];
+    let mut current = None;
+    for q in quotes {
+        let period = timeframe.period(local(q.timestamp), open);
+        match bars.last_mut() {
+            Some(bar) if current == Some(period) => {
+                bar.high = bar.high.max(q.high);
+                bar.low = bar.low.min(q.low);
+                bar.close = q.close;
+                bar.adjclose = q.adjclose;
+                bar.volume += q.volume;
+            }
+            _ => {
+                bars.push(q.clone());
+                current = Some(period);
+            }
+        }
+    }
+    bars
+}
+
+/// Resamples the quotes of each history into bars of a `Timeframe`, so that
+/// indicators downstream are computed over weeks or months instead of days.
+/// Everything else about a history, e.g. its splits, is passed on as it is.
+pub struct Resampler {
+    timeframe: Timeframe,
+    history_tx: mpsc::Sender<StockHistory>,
+    // latest known metadata per symbol, for histories that come without
+    instruments: HashMap<String, Instrument>,
+}
+
+impl Resampler {
+    pub fn new(timeframe: Timeframe, bufsize: usize) -> (Self, mpsc::Receiver<StockHistory>) {
+        let (history_tx, history_rx) = mpsc::channel(bufsize);
+        let resampler = Self {
+            timeframe,
+            history_tx,
+            instruments: HashMap::new(),
+        };
+        (resampler, history_rx)
+    }
+
+    pub fn resample(&mut self, mut history: StockHistory) -> StockHistory {
+        if let Some(instrument) = history.instrument.as_ref() {
+            self.instruments
+                .insert(history.symbol.clone(), instrument.clone());
+        }
+        history.quotes = resample(
+            &history.quotes,
+            self.timeframe,
+            self.instruments.get(&history.symbol),
+        );
+        history
+    }
+}
+
+impl Actor for Resampler {
+    type Context = Context<Self>;
+}
+
+impl Handler<StockHistory> for Resampler {
+    type Result = ();
+
+    fn handle(&mut self, history: StockHistory, _: &mut Context<Self>) {
+        let _span =
+            debug_span!("resample", tick = history.tick, symbol = %history.symbol).entered();
+        let bars = history.quotes.len();
+        let history = self.resample(history);
+        debug!(bars, resampled = history.quotes.len(), timeframe = %self.timeframe, "resampled");
+
+        let tx = self.history_tx.clone();
+        actix::spawn(async move {
+            let _ = tx.send(history).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(at: DateTime<Utc>, open: f64, close: f64, volume: u64) -> Quote {
+        Quote {
+            timestamp: at.timestamp() as u64,
+            open,
+            high: open.max(close) + 1.0,
+            low: open.min(close) - 1.0,
+            volume,
+            close,
+            adjclose: close,
+        }
+    }
+
+    fn new_york() -> Instrument {
+        Instrument {
+            currency: "USD".to_string(),
+            exchange: "NMS".to_string(),
+            timezone: "America/New_York".to_string(),
+            utc_offset: -14400,
+            market_price: 1.0,
+            regular_start: Utc.ymd(2021, 10, 1).and_hms(13, 30, 0),
+            regular_end: Utc.ymd(2021, 10, 1).and_hms(20, 0, 0),
+        }
+    }
+
+    #[test]
+    fn timeframes() {
+        assert_eq!("15m".parse(), Ok(Timeframe::Minutes(15)));
+        assert_eq!("1h".parse(), Ok(Timeframe::Minutes(60)));
+        assert_eq!("1w".parse(), Ok(Timeframe::Week));
+        assert_eq!("1mo".parse(), Ok(Timeframe::Month));
+        assert!("0m".parse::<Timeframe>().is_err());
+        assert!("2w".parse::<Timeframe>().is_err());
+        assert!("w".parse::<Timeframe>().is_err());
+        assert_eq!(Timeframe::Minutes(15).to_string(), "15m");
+
+        assert_eq!(Timeframe::Minutes(15).interval(), "15m");
+        assert_eq!(Timeframe::Minutes(120).interval(), "60m");
+        assert_eq!(Timeframe::Minutes(45).interval(), "15m");
+        assert_eq!(Timeframe::Minutes(7).interval(), "1m");
+        assert_eq!(Timeframe::Week.interval(), "1d");
+    }
+
+    #[test]
+    fn weekly() {
+        // Thu 30th to Tue 5th, in New York, where daily bars are stamped at
+        // 4:00 UTC
+        let daily: Vec<Quote> = [(9, 30), (10, 1), (10, 4), (10, 5)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(month, day))| {
+                let at = Utc.ymd(2021, month, day).and_hms(4, 0, 0);
+                bar(at, 10.0 + i as f64, 11.0 + i as f64, 100)
+            })
+            .collect();
+
+        let weekly = resample(&daily, Timeframe::Week, Some(&new_york()));
+        assert_eq!(weekly.len(), 2);
+        assert_eq!(weekly[0].timestamp, daily[0].timestamp);
+        assert_eq!(
+            (weekly[0].open, weekly[0].high, weekly[0].low),
+            (10.0, 13.0, 9.0)
+        );
+        assert_eq!((weekly[0].close, weekly[0].volume), (12.0, 200));
+        assert_eq!(weekly[1].timestamp, daily[2].timestamp);
+        assert_eq!((weekly[1].open, weekly[1].close), (12.0, 14.0));
+
+        let monthly = resample(&daily, Timeframe::Month, Some(&new_york()));
+        assert_eq!(monthly.len(), 2);
+        assert_eq!(monthly[1].open, 11.0);
+        assert_eq!(monthly[1].volume, 300);
+
+        // 2:00 UTC on the 1st is still September in New York
+        let late = [
+            bar(Utc.ymd(2021, 9, 30).and_hms(16, 0, 0), 1.0, 1.0, 1),
+            bar(Utc.ymd(2021, 10, 1).and_hms(2, 0, 0), 1.0, 1.0, 1),
+        ];
+        assert_eq!(
+            resample(&late, Timeframe::Month, Some(&new_york())).len(),
+            1
+        );
+        assert_eq!(resample(&late, Timeframe::Month, None).len(), 2);
+    }
+
+    #[test]
+    fn intraday() {
+        // every 15 minutes from 9:30 to 10:45 in New York
+        let open = Utc.ymd(2021, 10, 4).and_hms(13, 30, 0);
+        let bars: Vec<Quote> = (0..6)
+            .map(|i| bar(open + chrono::Duration::minutes(15 * i), 1.0, 1.0, 1))
+            .collect();
+
+        // aligned to the open, not to the full hour
+        let hourly = resample(&bars, Timeframe::Minutes(60), Some(&new_york()));
+        assert_eq!(
+            hourly.iter().map(|q| q.volume).collect::<Vec<_>>(),
+            vec![4, 2]
+        );
+        let hourly = resample(&bars, Timeframe::Minutes(60), None);
+        assert_eq!(
+            hourly.iter().map(|q| q.volume).collect::<Vec<_>>(),
+            vec![2, 4]
+        );
+    }
+
+    #[test]
+    fn resampler() {
+        use yahoo_finance_api::Split;
+
+        // Yahoo's bars are already adjusted for the split
+        let daily: Vec<Quote> = (4..=8)
+            .map(|day| bar(Utc.ymd(2021, 10, day).and_hms(4, 0, 0), 50.0, 50.0, 100))
+            .collect();
+        let split = Split {
+            date: daily[2].timestamp,
+            numerator: 2,
+            denominator: 1,
+            split_ratio: "2:1".to_string(),
+        };
+        let (mut resampler, _) = Resampler::new(Timeframe::Week, 1);
+        let history = resampler.resample(StockHistory {
+            symbol: "AAPL".to_string(),
+            from: Utc.ymd(2021, 10, 4).and_hms(0, 0, 0),
+            quotes: daily,
+            missing: vec![],
+            instrument: Some(new_york()),
+            splits: vec![split.clone()],
+            dividends: vec![],
+            tick: 1,
+        });
+        assert_eq!(history.quotes.len(), 1);
+        assert_eq!(
+            (history.quotes[0].open, history.quotes[0].close),
+            (50.0, 50.0)
+        );
+        assert_eq!(history.quotes[0].volume, 500);
+        assert_eq!(history.splits.len(), 1);
+        assert_eq!(history.splits[0].date, split.date);
+    }
+}
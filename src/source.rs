@@ -0,0 +1,37 @@
+/// Translates symbols as they appear in index lists into the spelling a quote
+/// source expects.
+pub trait SymbolNormalizer {
+    fn normalize(&self, symbol: &str) -> String;
+}
+
+/// Yahoo writes share classes with a dash (`BRK-B`), whereas most index lists
+/// use a dot (`BRK.B`). Other dotted suffixes denote exchanges (`VOW3.DE`,
+/// `7203.T`) and are left alone.
+pub struct YahooSymbols;
+
+impl SymbolNormalizer for YahooSymbols {
+    fn normalize(&self, symbol: &str) -> String {
+        let symbol = symbol.trim().to_uppercase();
+        match symbol.rsplit_once('.') {
+            Some((base, class)) if !base.is_empty() && matches!(class, "A" | "B" | "C") => {
+                format!("{}-{}", base, class)
+            }
+            _ => symbol,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yahoo() {
+        assert_eq!(YahooSymbols.normalize("BRK.B"), "BRK-B");
+        assert_eq!(YahooSymbols.normalize("bf.b"), "BF-B");
+        assert_eq!(YahooSymbols.normalize(" AAPL "), "AAPL");
+        assert_eq!(YahooSymbols.normalize("BRK-B"), "BRK-B");
+        assert_eq!(YahooSymbols.normalize("VOW3.DE"), "VOW3.DE");
+        assert_eq!(YahooSymbols.normalize("7203.T"), "7203.T");
+    }
+}
@@ -0,0 +1,109 @@
+use std::{fs, path::Path, sync::Arc};
+
+use arrow::{
+    array::{Float64Array, TimestampSecondArray, UInt64Array},
+    record_batch::RecordBatch,
+};
+use chrono::prelude::*;
+use parquet::{
+    arrow::{ArrowReader, ParquetFileArrowReader},
+    errors::{ParquetError, Result},
+    file::reader::SerializedFileReader,
+};
+use yahoo_finance_api as yahoo;
+
+use crate::messages::*;
+
+/// Reads back the quotes an `Exporter` has written to `dir`, as one history
+/// per symbol, sorted by symbol. Each history starts at its first quote.
+pub fn read_histories(dir: &Path) -> Result<Vec<StockHistory>> {
+    let mut histories = vec![];
+    for symbol_dir in sorted_entries(&dir.join("quotes"))? {
+        let symbol = match partition_value(&symbol_dir, "symbol") {
+            Some(symbol) => symbol,
+            None => continue,
+        };
+
+        let mut quotes = vec![];
+        for date_dir in sorted_entries(&symbol_dir)? {
+            for file in sorted_entries(&date_dir)? {
+                if file.extension().is_some_and(|ext| ext == "parquet") {
+                    read_quotes(&file, &mut quotes)?;
+                }
+            }
+        }
+        quotes.sort_by_key(|q| q.timestamp);
+        quotes.dedup_by_key(|q| q.timestamp);
+
+        if let Some(first) = quotes.first() {
+            histories.push(StockHistory {
+                symbol,
+                from: Utc.timestamp(first.timestamp as i64, 0),
+                quotes,
+                // the store only keeps quotes
+                missing: vec![],
+                instrument: None,
+                splits: vec![],
+                dividends: vec![],
+                tick: 0,
+            });
+        }
+    }
+    Ok(histories)
+}
+
+fn sorted_entries(dir: &Path) -> Result<Vec<std::path::PathBuf>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+// `symbol=AAPL` -> `AAPL`
+fn partition_value(dir: &Path, key: &str) -> Option<String> {
+    let name = dir.file_name()?.to_str()?;
+    let (k, v) = name.split_once('=')?;
+    if k == key {
+        Some(v.to_string())
+    } else {
+        None
+    }
+}
+
+fn read_quotes(path: &Path, quotes: &mut Vec<yahoo::Quote>) -> Result<()> {
+    let reader = SerializedFileReader::new(fs::File::open(path)?)?;
+    let mut reader = ParquetFileArrowReader::new(Arc::new(reader));
+    for batch in reader.get_record_reader(1024)? {
+        let batch = batch?;
+        let timestamp = column::<TimestampSecondArray>(&batch, "timestamp")?;
+        let open = column::<Float64Array>(&batch, "open")?;
+        let high = column::<Float64Array>(&batch, "high")?;
+        let low = column::<Float64Array>(&batch, "low")?;
+        let close = column::<Float64Array>(&batch, "close")?;
+        let adjclose = column::<Float64Array>(&batch, "adjclose")?;
+        let volume = column::<UInt64Array>(&batch, "volume")?;
+
+        for i in 0..batch.num_rows() {
+            quotes.push(yahoo::Quote {
+                timestamp: timestamp.value(i) as u64,
+                open: open.value(i),
+                high: high.value(i),
+                low: low.value(i),
+                volume: volume.value(i),
+                close: close.value(i),
+                adjclose: adjclose.value(i),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn column<'a, T: 'static>(batch: &'a RecordBatch, name: &str) -> Result<&'a T> {
+    let index = batch.schema().index_of(name)?;
+    batch
+        .column(index)
+        .as_any()
+        .downcast_ref::<T>()
+        .ok_or_else(|| ParquetError::General(format!("unexpected type of column `{}`", name)))
+}
@@ -0,0 +1,114 @@
+use std::{fs, io, path::Path};
+
+/// Parses a list of symbols separated by commas and/or newlines. Everything
+/// after a `#` up to the end of the line is a comment. Duplicates are removed,
+/// keeping the first occurrence.
+pub fn parse_symbols(content: &str) -> Vec<String> {
+    let mut symbols = vec![];
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        union(
+            &mut symbols,
+            line.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from),
+        );
+    }
+    symbols
+}
+
+pub fn read_symbols(path: &Path) -> io::Result<Vec<String>> {
+    Ok(parse_symbols(&fs::read_to_string(path)?))
+}
+
+/// Appends the symbols from `other` not yet in `symbols`.
+pub fn union<I: IntoIterator<Item = String>>(symbols: &mut Vec<String>, other: I) {
+    for symbol in other {
+        if !symbols.contains(&symbol) {
+            symbols.push(symbol);
+        }
+    }
+}
+
+pub fn exclude(symbols: &mut Vec<String>, other: &[String]) {
+    symbols.retain(|s| !other.contains(s));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOp {
+    Union,
+    Exclude,
+}
+
+/// Splits a set expression like `sp500 - financials + @custom.txt` into its
+/// operands, each with the operation combining it with everything before. The
+/// operators need to be separated by whitespace, since symbols like `BRK-B`
+/// may contain them.
+pub fn parse_set_expression(expr: &str) -> Result<Vec<(SetOp, &str)>, String> {
+    let mut operands = vec![];
+    let mut op = Some(SetOp::Union);
+    for token in expr.split_whitespace() {
+        match (token, op) {
+            ("+", None) => op = Some(SetOp::Union),
+            ("-", None) => op = Some(SetOp::Exclude),
+            ("+", Some(_)) | ("-", Some(_)) => {
+                return Err(format!("unexpected `{}` in `{}`", token, expr))
+            }
+            (operand, Some(o)) => {
+                operands.push((o, operand));
+                op = None;
+            }
+            (operand, None) => {
+                return Err(format!(
+                    "expected `+` or `-` before `{}` in `{}`",
+                    operand, expr
+                ))
+            }
+        }
+    }
+    if operands.is_empty() {
+        return Err("empty set expression".to_string());
+    }
+    if op.is_some() {
+        return Err(format!("expected a list after `{}`", expr.trim()));
+    }
+    Ok(operands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_symbols("MMM,AOS,ABT\n"), vec!["MMM", "AOS", "ABT"]);
+        assert_eq!(
+            parse_symbols("# banks\nJPM\n  BAC # Bank of America\n\nC, JPM,BRK-B"),
+            vec!["JPM", "BAC", "C", "BRK-B"]
+        );
+        assert!(parse_symbols("# nothing here").is_empty());
+    }
+
+    #[test]
+    fn set_expression() {
+        assert_eq!(
+            parse_set_expression("sp500 - financials + @custom.txt"),
+            Ok(vec![
+                (SetOp::Union, "sp500"),
+                (SetOp::Exclude, "financials"),
+                (SetOp::Union, "@custom.txt"),
+            ])
+        );
+        assert!(parse_set_expression("").is_err());
+        assert!(parse_set_expression("sp500 financials").is_err());
+        assert!(parse_set_expression("sp500 -").is_err());
+        assert!(parse_set_expression("- sp500").is_err());
+        assert!(parse_set_expression("sp500 + - financials").is_err());
+
+        let mut symbols = vec!["A".to_string(), "B".to_string()];
+        union(&mut symbols, vec!["B".to_string(), "C".to_string()]);
+        exclude(&mut symbols, &["A".to_string()]);
+        assert_eq!(symbols, vec!["B", "C"]);
+    }
+}
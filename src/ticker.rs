@@ -1,17 +1,33 @@
 use actix::prelude::*;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
 use tokio::{sync::mpsc, time};
 
 use crate::messages::*;
 
 pub struct Ticker {
     interval: time::Duration,
+    schedule: Option<Schedule>,
     tx_out: mpsc::Sender<StartFetch>,
 }
 
 impl Ticker {
     pub fn new(interval: time::Duration, bufsize: usize) -> (Self, mpsc::Receiver<StartFetch>) {
         let (tx_out, rx_out) = mpsc::channel(bufsize);
-        (Ticker { interval, tx_out }, rx_out)
+        (
+            Ticker {
+                interval,
+                schedule: None,
+                tx_out,
+            },
+            rx_out,
+        )
+    }
+
+    /// Only tick while `schedule` is active.
+    pub fn with_schedule(mut self, schedule: Option<Schedule>) -> Self {
+        self.schedule = schedule;
+        self
     }
 }
 
@@ -23,12 +39,61 @@ impl Handler<StartTicking> for Ticker {
     type Result = ();
     fn handle(&mut self, _: StartTicking, _: &mut Context<Self>) {
         let mut interval = time::interval(self.interval);
+        let schedule = self.schedule.clone();
         let tx_out = self.tx_out.clone();
         actix::spawn(async move {
             loop {
                 interval.tick().await;
-                let _ = tx_out.send(StartFetch).await;
+                if schedule.as_ref().is_none_or(|s| s.is_active(Utc::now())) {
+                    let _ = tx_out.send(StartFetch).await;
+                }
             }
         });
     }
 }
+
+/// Days and UTC times of day during which fetching makes sense, e.g. the
+/// trading hours of an exchange.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Schedule {
+    #[serde(default = "Schedule::weekdays")]
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl Schedule {
+    fn weekdays() -> Vec<Weekday> {
+        vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+        ]
+    }
+
+    pub fn is_active(&self, at: DateTime<Utc>) -> bool {
+        self.days.contains(&at.weekday()) && self.start <= at.time() && at.time() < self.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule() {
+        let schedule = Schedule {
+            days: Schedule::weekdays(),
+            start: NaiveTime::from_hms(13, 30, 0),
+            end: NaiveTime::from_hms(20, 0, 0),
+        };
+        // 2021-10-15 is a Friday
+        assert!(schedule.is_active(Utc.ymd(2021, 10, 15).and_hms(13, 30, 0)));
+        assert!(schedule.is_active(Utc.ymd(2021, 10, 15).and_hms(19, 59, 59)));
+        assert!(!schedule.is_active(Utc.ymd(2021, 10, 15).and_hms(20, 0, 0)));
+        assert!(!schedule.is_active(Utc.ymd(2021, 10, 15).and_hms(9, 0, 0)));
+        assert!(!schedule.is_active(Utc.ymd(2021, 10, 16).and_hms(15, 0, 0)));
+    }
+}
@@ -1,16 +1,210 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
 use actix::prelude::*;
+use chrono::prelude::*;
 use tokio::{io, sync::mpsc};
+use tracing::{debug, debug_span};
+
+use yahoo_finance_api::Quote;
 
-use crate::messages::*;
+use crate::{calendar::*, error::*, indicators::*, messages::*, subscribe};
+
+/// What the transformer has seen of a symbol's history on earlier ticks.
+#[derive(Clone)]
+struct SymbolState {
+    // bars folded into the state, all but the latest one, which may still
+    // change until the next one shows up
+    committed: usize,
+    // first and last folded bar as fetched, and the number of splits, to tell
+    // whether a new history extends the folded one
+    first: Option<Quote>,
+    last: Option<Quote>,
+    splits: usize,
+    open: f64,
+    high: f64,
+    low: f64,
+    sma30: Sma,
+    ema20: Ema,
+    rsi14: Rsi,
+    atr14: Atr,
+}
+
+impl SymbolState {
+    fn new() -> Self {
+        Self {
+            committed: 0,
+            first: None,
+            last: None,
+            splits: 0,
+            open: 0.0,
+            high: f64::MIN,
+            low: f64::MAX,
+            sma30: Sma::new(30),
+            ema20: Ema::new(20),
+            rsi14: Rsi::new(14),
+            atr14: Atr::new(14),
+        }
+    }
+
+    /// Whether `history` starts with the folded bars, unchanged, and has at
+    /// least one more.
+    fn is_extended_by(&self, history: &StockHistory) -> bool {
+        self.committed < history.quotes.len()
+            && self.splits == history.splits.len()
+            && (self.committed == 0
+                || (history.quotes.first() == self.first.as_ref()
+                    && history.quotes.get(self.committed - 1) == self.last.as_ref()))
+    }
+
+    /// Folds in the next bar.
+    fn fold(&mut self, q: &Quote) {
+        if self.committed == 0 {
+            self.open = q.open;
+            self.high = q.open;
+            self.low = q.open;
+        }
+        self.high = self.high.max(q.high);
+        self.low = self.low.min(q.low);
+        self.sma30.update(q.adjclose);
+        self.ema20.update(q.adjclose);
+        self.rsi14.update(q.adjclose);
+        self.atr14.update(q);
+        self.committed += 1;
+    }
+}
 
+#[derive(Clone)]
 pub struct Transformer {
     info_tx: mpsc::Sender<StockInfo>,
+    // latest known metadata per symbol, for histories that come without
+    instruments: HashMap<String, Instrument>,
+    states: HashMap<String, SymbolState>,
+    err_tx: Option<mpsc::Sender<Error>>,
+    sma30: bool,
+    ema20: bool,
+    rsi14: bool,
+    atr14: bool,
+    calendar: Option<Calendar>,
 }
 
 impl Transformer {
     pub fn new(bufsize: usize) -> Result<(Self, mpsc::Receiver<StockInfo>), io::Error> {
         let (info_tx, info_rx) = mpsc::channel(bufsize);
-        Ok((Self { info_tx }, info_rx))
+        Ok((
+            Self {
+                info_tx,
+                instruments: HashMap::new(),
+                states: HashMap::new(),
+                err_tx: None,
+                sma30: true,
+                ema20: true,
+                rsi14: true,
+                atr14: true,
+                calendar: None,
+            },
+            info_rx,
+        ))
+    }
+
+    /// Whether to compute the 30-day simple moving average (default: true).
+    pub fn with_sma30(mut self, enabled: bool) -> Self {
+        self.sma30 = enabled;
+        self
+    }
+
+    /// Computes only the indicators in `names`, see `INDICATORS`
+    /// (default: all of them).
+    pub fn with_indicators<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        let enabled = |name: &str| names.iter().any(|n| n.as_ref() == name);
+        self.sma30 = enabled("sma30");
+        self.ema20 = enabled("ema20");
+        self.rsi14 = enabled("rsi14");
+        self.atr14 = enabled("atr14");
+        self
+    }
+
+    /// Marks indicators whose window misses trading days of `calendar`.
+    pub fn with_calendar(mut self, calendar: Option<Calendar>) -> Self {
+        self.calendar = calendar;
+        self
+    }
+
+    /// Reports histories that cannot be transformed to `err_tx`, instead of
+    /// silently dropping them.
+    pub fn with_errors(mut self, err_tx: mpsc::Sender<Error>) -> Self {
+        self.err_tx = Some(err_tx);
+        self
+    }
+}
+
+impl Transformer {
+    /// Computes the `StockInfo` of a non-empty history. Only bars that were
+    /// not part of the previous history for the symbol are folded into its
+    /// state, the state is rebuilt if the new history does not extend it, e.g.
+    /// after a split, for which Yahoo adjusts all earlier bars.
+    fn transform(&mut self, history: StockHistory) -> StockInfo {
+        let state = self
+            .states
+            .entry(history.symbol.clone())
+            .or_insert_with(SymbolState::new);
+        if !state.is_extended_by(&history) {
+            if state.committed > 0 {
+                debug!(bars = state.committed, "history changed, recomputing");
+            }
+            *state = SymbolState::new();
+        }
+
+        let new = &history.quotes[state.committed..];
+        let (latest, complete) = new.split_last().unwrap();
+        complete.iter().for_each(|q| state.fold(q));
+        let l = history.quotes.len();
+        if state.first.is_none() {
+            state.first = history.quotes[..state.committed].first().cloned();
+        }
+        state.last = history.quotes[..state.committed].last().cloned();
+        state.splits = history.splits.len();
+
+        let mut current = state.clone();
+        current.fold(latest);
+        debug!(bars = l, folded = complete.len(), "transformed");
+
+        let enabled = |enabled: bool, value: Option<f64>| value.filter(|_| enabled);
+        let sma30 = enabled(self.sma30, current.sma30.value());
+
+        if let Some(instrument) = history.instrument.as_ref() {
+            self.instruments
+                .insert(history.symbol.clone(), instrument.clone());
+        }
+        let instrument = self.instruments.get(&history.symbol).cloned();
+
+        let sma30_gapped = match (sma30, self.calendar.as_ref()) {
+            (Some(_), Some(calendar)) => {
+                let dates = bar_dates(&history.quotes[l - 30..], instrument.as_ref());
+                !calendar.gaps(&dates).is_empty()
+            }
+            _ => false,
+        };
+
+        StockInfo {
+            symbol: history.symbol,
+            from: history.from,
+            timestamp: Utc.timestamp(latest.timestamp as i64, 0),
+            open: current.open,
+            high: current.high,
+            low: current.low,
+            close: (latest.adjclose / current.open - 1.0) * 100.0,
+            price: latest.close,
+            sma30,
+            sma30_gapped,
+            ema20: enabled(self.ema20, current.ema20.value()),
+            rsi14: enabled(self.rsi14, current.rsi14.value()),
+            atr14: enabled(self.atr14, current.atr14.value()),
+            instrument,
+            tick: history.tick,
+        }
     }
 }
 
@@ -18,43 +212,92 @@ impl Actor for Transformer {
     type Context = Context<Self>;
 }
 
+/// Histories that may wait for each worker of a `TransformerPool`.
+const SHARD_BUFSIZE: usize = 64;
+
+impl Transformer {
+    /// Starts `workers` copies of this transformer, each on its own arbiter,
+    /// i.e. thread. They share the receiver of `new`.
+    pub fn start_pool(self, workers: usize) -> Addr<TransformerPool> {
+        let arbiters: Vec<Arbiter> = (0..workers.max(1)).map(|_| Arbiter::new()).collect();
+        let shards = arbiters
+            .iter()
+            .map(|arbiter| {
+                let worker = self.clone();
+                let worker = Transformer::start_in_arbiter(&arbiter.handle(), move |_| worker);
+                let (tx, rx) = mpsc::channel(SHARD_BUFSIZE);
+                subscribe(worker, rx);
+                tx
+            })
+            .collect();
+        TransformerPool { shards, arbiters }.start()
+    }
+}
+
+/// Routes each history to one of several `Transformer`s by the hash of its
+/// symbol, so that all histories of a symbol are transformed in order by the
+/// worker that holds its state. See `Transformer::start_pool`.
+///
+/// Each worker is fed through a bounded channel. A history is only accepted
+/// once there is room in its worker's channel, so a worker that falls behind
+/// holds back whatever feeds the pool.
+pub struct TransformerPool {
+    shards: Vec<mpsc::Sender<StockHistory>>,
+    arbiters: Vec<Arbiter>,
+}
+
+impl TransformerPool {
+    fn shard(&self, symbol: &str) -> &mpsc::Sender<StockHistory> {
+        let mut hasher = DefaultHasher::new();
+        symbol.hash(&mut hasher);
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+}
+
+impl Actor for TransformerPool {
+    type Context = Context<Self>;
+
+    fn stopped(&mut self, _: &mut Context<Self>) {
+        for arbiter in self.arbiters.iter() {
+            arbiter.stop();
+        }
+    }
+}
+
+impl Handler<StockHistory> for TransformerPool {
+    type Result = ResponseFuture<()>;
+
+    fn handle(&mut self, history: StockHistory, _: &mut Context<Self>) -> Self::Result {
+        let tx = self.shard(&history.symbol).clone();
+        Box::pin(async move {
+            // only fails once the pool is shutting down
+            let _ = tx.send(history).await;
+        })
+    }
+}
+
 impl Handler<StockHistory> for Transformer {
     type Result = ();
     fn handle(&mut self, history: StockHistory, _: &mut Context<Self>) {
-        let l = history.quotes.len();
-        let open = history.quotes[0].open;
-        let mut high = open;
-        let mut low = open;
-        for q in history.quotes.iter() {
-            if q.high > high {
-                high = q.high;
-            }
-            if q.low < low {
-                low = q.low;
+        let _span =
+            debug_span!("transform", tick = history.tick, symbol = %history.symbol).entered();
+
+        if history.quotes.is_empty() {
+            if let Some(err_tx) = self.err_tx.clone() {
+                let e = Error::new(
+                    Some(history.symbol),
+                    Stage::Transform,
+                    ErrorKind::EmptyHistory,
+                )
+                .with_tick(history.tick);
+                actix::spawn(async move {
+                    let _ = err_tx.send(e).await;
+                });
             }
+            return;
         }
 
-        let sma30 = if l < 30 {
-            None
-        } else {
-            Some(
-                history.quotes[l - 30..]
-                    .iter()
-                    .map(|q| q.adjclose)
-                    .fold(0.0, |sum, close| sum + close)
-                    / 30.0,
-            )
-        };
-
-        let info = StockInfo {
-            symbol: history.symbol,
-            from: history.from,
-            open,
-            high,
-            low,
-            close: (history.quotes[l - 1].adjclose / open - 1.0) * 100.0,
-            sma30,
-        };
+        let info = self.transform(history);
 
         let tx = self.info_tx.clone();
         actix::spawn(async move {
@@ -83,7 +326,6 @@ mod tests {
 
     #[actix_rt::test]
     async fn transform() {
-        use chrono::prelude::*;
         use yahoo_finance_api::Quote;
         let (transformer, mut rx) = Transformer::new(1).unwrap();
         let transformer = transformer.start();
@@ -94,6 +336,11 @@ mod tests {
             .send(StockHistory {
                 symbol: "AAPL".to_string(),
                 from,
+                missing: vec![],
+                instrument: None,
+                splits: vec![],
+                dividends: vec![],
+                tick: 0,
                 quotes: vec![
                     ohlcv!(o 1.0, h 3.5, l 1.0, c 2.0, v 10),
                     ohlcv!(o 2.0, h 3.1, l 0.9, c 3.0, v 10),
@@ -104,7 +351,7 @@ mod tests {
             .unwrap();
         assert_eq!(
             rx.recv().await.unwrap().fmt_csv(),
-            "2021-01-01T00:00:00+00:00,AAPL,1.00,210.00,0.90,3.50,"
+            "2021-01-01T00:00:00+00:00,AAPL,1.00,210.00,0.90,3.50,,,,,1970-01-01T00:00:00+00:00"
         );
 
         // no SMA until at least 30 values
@@ -112,13 +359,18 @@ mod tests {
             .send(StockHistory {
                 symbol: "AAPL".to_string(),
                 from,
+                missing: vec![],
+                instrument: None,
+                splits: vec![],
+                dividends: vec![],
+                tick: 0,
                 quotes: vec![ohlcv!(o 1.0, h 1.0, l 1.0, c 1.0, v 00); 29],
             })
             .await
             .unwrap();
         assert_eq!(
             rx.recv().await.unwrap().fmt_csv(),
-            "2021-01-01T00:00:00+00:00,AAPL,1.00,0.00,1.00,1.00,"
+            "2021-01-01T00:00:00+00:00,AAPL,1.00,0.00,1.00,1.00,,1.00,50.00,0.00,1970-01-01T00:00:00+00:00"
         );
 
         // has SMA once we have 30 values
@@ -126,13 +378,153 @@ mod tests {
             .send(StockHistory {
                 symbol: "AAPL".to_string(),
                 from,
+                missing: vec![],
+                instrument: None,
+                splits: vec![],
+                dividends: vec![],
+                tick: 0,
                 quotes: vec![ohlcv!(o 1.0, h 1.0, l 1.0, c 1.0, v 00); 30],
             })
             .await
             .unwrap();
         assert_eq!(
             rx.recv().await.unwrap().fmt_csv(),
-            "2021-01-01T00:00:00+00:00,AAPL,1.00,0.00,1.00,1.00,1.00"
+            "2021-01-01T00:00:00+00:00,AAPL,1.00,0.00,1.00,1.00,1.00,1.00,50.00,0.00,1970-01-01T00:00:00+00:00"
         );
     }
+
+    // what `transform` computed from the full history before it kept state
+    fn recompute(quotes: &[Quote]) -> (f64, f64, f64, Option<f64>) {
+        let open = quotes[0].open;
+        let high = quotes.iter().fold(open, |high, q| high.max(q.high));
+        let low = quotes.iter().fold(open, |low, q| low.min(q.low));
+        let l = quotes.len();
+        let sma30 = if l < 30 {
+            None
+        } else {
+            Some(quotes[l - 30..].iter().map(|q| q.adjclose).sum::<f64>() / 30.0)
+        };
+        (open, high, low, sma30)
+    }
+
+    #[test]
+    fn incremental() {
+        use yahoo_finance_api::Split;
+
+        let (mut transformer, _) = Transformer::new(1).unwrap();
+        let day = 24 * 60 * 60;
+        let bars: Vec<Quote> = (0..80)
+            .map(|i| {
+                let close = 100.0 + (i as f64 * 0.3).sin() * 5.0;
+                Quote {
+                    timestamp: i * day,
+                    open: close - 0.5,
+                    high: close + 1.0 + (i % 7) as f64,
+                    low: close - 1.0 - (i % 5) as f64,
+                    volume: 100,
+                    close,
+                    adjclose: close,
+                }
+            })
+            .collect();
+        let split = Split {
+            date: 50 * day + 1,
+            numerator: 2,
+            denominator: 1,
+            split_ratio: "2:1".to_string(),
+        };
+
+        // one more bar per tick, the latest of which is still trading, a
+        // repeated tick, and a split showing up after the fact, along with
+        // the earlier bars adjusted for it, as Yahoo does
+        let ticks = (1..=80).chain(std::iter::once(80)).enumerate();
+        for (tick, l) in ticks {
+            let mut quotes = bars[..l].to_vec();
+            let latest = quotes.last_mut().unwrap();
+            latest.close += 0.25;
+            latest.adjclose += 0.25;
+            latest.high += 2.0;
+            let splits = if l > 60 { vec![split.clone()] } else { vec![] };
+            for q in quotes
+                .iter_mut()
+                .filter(|q| l > 60 && q.timestamp < split.date)
+            {
+                q.open /= 2.0;
+                q.high /= 2.0;
+                q.low /= 2.0;
+                q.close /= 2.0;
+                q.adjclose /= 2.0;
+            }
+
+            let (open, high, low, sma30) = recompute(&quotes);
+            let bars_as_fetched = quotes.clone();
+            let info = transformer.transform(StockHistory {
+                symbol: "AAPL".to_string(),
+                quotes,
+                missing: vec![],
+                instrument: None,
+                splits,
+                dividends: vec![],
+                from: Utc.timestamp(0, 0),
+                tick: tick as u64,
+            });
+            assert_eq!(transformer.states["AAPL"].committed, l - 1);
+            assert_eq!((info.open, info.high, info.low), (open, high, low));
+            let (mut ema20, mut rsi14, mut atr14) = (Ema::new(20), Rsi::new(14), Atr::new(14));
+            for q in bars_as_fetched.iter() {
+                ema20.update(q.adjclose);
+                rsi14.update(q.adjclose);
+                atr14.update(q);
+            }
+            for (a, b) in [
+                (info.sma30, sma30),
+                (info.ema20, ema20.value()),
+                (info.rsi14, rsi14.value()),
+                (info.atr14, atr14.value()),
+            ] {
+                match (a, b) {
+                    (Some(a), Some(b)) => assert!((a - b).abs() < 1e-9, "{} != {}", a, b),
+                    (a, b) => assert_eq!(a, b),
+                }
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn pool() {
+        let (transformer, mut rx) = Transformer::new(1).unwrap();
+        let pool = transformer.start_pool(3);
+        let symbols: Vec<String> = (0..10).map(|i| format!("S{}", i)).collect();
+        for tick in 1..=2 {
+            for symbol in symbols.iter() {
+                pool.send(StockHistory {
+                    symbol: symbol.clone(),
+                    quotes: vec![ohlcv!(o 1.0, h 1.0, l 1.0, c 1.0, v 1); 29 + tick],
+                    missing: vec![],
+                    instrument: None,
+                    splits: vec![],
+                    dividends: vec![],
+                    from: Utc.timestamp(0, 0),
+                    tick: tick as u64,
+                })
+                .await
+                .unwrap();
+            }
+        }
+
+        let mut infos = vec![];
+        for _ in 0..20 {
+            let info = rx.recv().await.unwrap();
+            infos.push((info.symbol, info.tick, info.sma30));
+        }
+        // each symbol's histories arrive in order, whichever worker it is on
+        for symbol in symbols.iter() {
+            let ticks: Vec<(u64, Option<f64>)> = infos
+                .iter()
+                .filter(|(s, _, _)| s == symbol)
+                .map(|(_, tick, sma30)| (*tick, *sma30))
+                .collect();
+            assert_eq!(ticks, vec![(1, Some(1.0)), (2, Some(1.0))]);
+        }
+    }
 }
@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+
+use actix::prelude::*;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::error;
+
+use crate::messages::*;
+
+struct Client {
+    symbols: Option<HashSet<String>>,
+    tx: mpsc::Sender<StockInfo>,
+}
+
+/// Pushes each `StockInfo` to all connected WebSocket clients whose filter
+/// matches its symbol. Each client has its own bounded buffer, and messages
+/// for a client whose buffer is full are dropped, so a slow client never
+/// stalls the pipeline.
+pub struct Hub {
+    client_bufsize: usize,
+    clients: Vec<Client>,
+}
+
+impl Hub {
+    pub fn new(client_bufsize: usize) -> Self {
+        Self {
+            client_bufsize,
+            clients: vec![],
+        }
+    }
+
+    fn connect(&mut self, symbols: Option<HashSet<String>>) -> mpsc::Receiver<StockInfo> {
+        let (tx, rx) = mpsc::channel(self.client_bufsize);
+        self.clients.push(Client { symbols, tx });
+        rx
+    }
+
+    // forgets clients that have gone away
+    fn publish(&mut self, info: &StockInfo) {
+        self.clients.retain(|client| {
+            let wanted = client
+                .symbols
+                .as_ref()
+                .is_none_or(|symbols| symbols.contains(&info.symbol));
+            if !wanted {
+                return true;
+            }
+            match client.tx.try_send(info.clone()) {
+                Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => true,
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
+
+impl Actor for Hub {
+    type Context = Context<Self>;
+}
+
+impl Handler<StockInfo> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, info: StockInfo, _: &mut Context<Self>) {
+        self.publish(&info);
+    }
+}
+
+// registers a new client, `None` meaning all symbols
+#[derive(Message)]
+#[rtype(result = "mpsc::Receiver<StockInfo>")]
+struct Connect(Option<HashSet<String>>);
+
+impl Handler<Connect> for Hub {
+    type Result = MessageResult<Connect>;
+
+    fn handle(&mut self, Connect(symbols): Connect, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.connect(symbols))
+    }
+}
+
+struct Session {
+    rx: Option<mpsc::Receiver<StockInfo>>,
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(rx) = self.rx.take() {
+            ctx.add_stream(ReceiverStream::new(rx));
+        }
+    }
+}
+
+impl StreamHandler<StockInfo> for Session {
+    fn handle(&mut self, info: StockInfo, ctx: &mut Self::Context) {
+        match serde_json::to_string(&info) {
+            Ok(json) => ctx.text(json),
+            Err(e) => error!(sink = "ws", symbol = %info.symbol, error = %e, "failed to serialize"),
+        }
+    }
+
+    // the hub dropped us, there is nothing more to send
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        ctx.close(None);
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(bytes)) => ctx.pong(&bytes),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Err(_) => ctx.stop(),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Filter {
+    symbols: Option<String>,
+}
+
+/// `GET /ws?symbols=AAPL,MSFT`: upgrades to a WebSocket that receives each new
+/// `StockInfo` for the given symbols (all symbols if omitted) as JSON.
+pub(crate) async fn connect(
+    req: HttpRequest,
+    stream: web::Payload,
+    hub: web::Data<Addr<Hub>>,
+    filter: web::Query<Filter>,
+) -> actix_web::Result<HttpResponse> {
+    let symbols = filter.into_inner().symbols.map(|symbols| {
+        symbols
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    });
+    let rx = hub
+        .send(Connect(symbols))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    ws::start(Session { rx: Some(rx) }, &req, stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    fn info(symbol: &str, price: f64) -> StockInfo {
+        StockInfo {
+            symbol: symbol.to_string(),
+            from: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            timestamp: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            open: 1.0,
+            high: price,
+            low: price,
+            close: 0.0,
+            price,
+            sma30: None,
+            sma30_gapped: false,
+            ema20: None,
+            rsi14: None,
+            atr14: None,
+            instrument: None,
+            tick: 0,
+        }
+    }
+
+    fn received(rx: &mut mpsc::Receiver<StockInfo>) -> Vec<(String, f64)> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|info| (info.symbol, info.price))
+            .collect()
+    }
+
+    #[test]
+    fn filter() {
+        let mut hub = Hub::new(8);
+        let mut all = hub.connect(None);
+        let mut aapl = hub.connect(Some(std::iter::once("AAPL".to_string()).collect()));
+        hub.publish(&info("AAPL", 1.0));
+        hub.publish(&info("MSFT", 2.0));
+        assert_eq!(
+            received(&mut all),
+            vec![("AAPL".to_string(), 1.0), ("MSFT".to_string(), 2.0)]
+        );
+        assert_eq!(received(&mut aapl), vec![("AAPL".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn slow_client() {
+        let mut hub = Hub::new(2);
+        let mut slow = hub.connect(None);
+        for price in 1..=4 {
+            hub.publish(&info("AAPL", price as f64));
+        }
+        // the overflow is dropped, the client stays connected
+        assert_eq!(
+            received(&mut slow),
+            vec![("AAPL".to_string(), 1.0), ("AAPL".to_string(), 2.0)]
+        );
+        hub.publish(&info("AAPL", 5.0));
+        assert_eq!(received(&mut slow), vec![("AAPL".to_string(), 5.0)]);
+    }
+
+    #[test]
+    fn closed_client() {
+        let mut hub = Hub::new(2);
+        let rx = hub.connect(Some(std::iter::once("AAPL".to_string()).collect()));
+        let _open = hub.connect(None);
+        drop(rx);
+        // only noticed once there is something to send
+        hub.publish(&info("MSFT", 1.0));
+        assert_eq!(hub.clients.len(), 2);
+        hub.publish(&info("AAPL", 1.0));
+        assert_eq!(hub.clients.len(), 1);
+    }
+}
@@ -1,110 +1,746 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
 
 use actix::prelude::*;
 use chrono::prelude::*;
-use tokio::{sync::mpsc, time};
-use yahoo::{YahooConnector, YahooError};
-use yahoo_finance_api as yahoo;
+use tokio::{
+    sync::{mpsc, Semaphore},
+    time,
+};
+use tracing::{debug, debug_span, info_span, warn, Instrument as _, Span};
+use yahoo_finance_api::YahooError;
 
-use crate::messages::*;
+use crate::{breaker::*, error::*, messages::*, metrics::*, quotes::*};
 
-pub struct Fetcher {
-    connector: Arc<YahooConnector>,
+/// Number of `FetchSingle` workers, unless set with `with_workers`.
+pub const FETCH_WORKERS: usize = 32;
+
+/// The name the rest of the pipeline knows the `FetchTrigger` by.
+pub type Fetcher = FetchTrigger;
+
+/// On each `StartFetch`, hands a `Fetch` per symbol to a pool of `FetchSingle`
+/// workers, spaced out by the debounce. Each worker fetches one symbol at a
+/// time, so the number of workers bounds the number of requests in flight.
+pub struct FetchTrigger {
+    source: Arc<dyn QuoteSource>,
     symbols: Vec<String>,
     from: DateTime<Utc>,
+    to: Option<DateTime<Utc>>,
+    interval: &'static str,
     debounce: time::Duration,
-    err_tx: mpsc::Sender<YahooError>,
+    timeout: Option<time::Duration>,
+    err_tx: mpsc::Sender<Error>,
     hist_tx: mpsc::Sender<StockHistory>,
+    metrics: Option<Metrics>,
+    // number of the last `StartFetch`, to correlate everything it causes
+    tick: u64,
+    // consecutive failures per symbol
+    failures: Arc<Mutex<HashMap<String, u32>>>,
+    // symbols whose fetch has been handed out but not completed yet
+    in_flight: HashSet<String>,
+    breaker: Option<Arc<Mutex<CircuitBreaker>>>,
+    // number of probes so far, to rotate through the symbols
+    probes: usize,
+    n_workers: usize,
+    // started with the trigger, so that they get its final settings
+    workers: Vec<Addr<FetchSingle>>,
+    // workers without a fetch, and fetches that are due but wait for one, so
+    // that a hanging fetch holds up its worker but no other symbol
+    idle: Vec<usize>,
+    queue: VecDeque<Fetch>,
+    // fetches still running per tick, when the tick started, and its span,
+    // which stays open until the last one has completed
+    ticks: HashMap<u64, (usize, time::Instant, Span)>,
 }
 
-impl Fetcher {
+impl FetchTrigger {
+    /// Symbols are normalized to the spelling the source expects, see
+    /// `QuoteSource::normalize`.
     pub fn new(
         symbols: Vec<String>,
         from: DateTime<Utc>,
         debounce: time::Duration,
-    ) -> (
-        Self,
-        mpsc::Receiver<StockHistory>,
-        mpsc::Receiver<YahooError>,
-    ) {
-        let connector = Arc::new(YahooConnector::new());
+    ) -> (Self, mpsc::Receiver<StockHistory>, mpsc::Receiver<Error>) {
+        let source: Arc<dyn QuoteSource> = Arc::new(YahooSource::new());
+        let symbols: Vec<String> = symbols.iter().map(|s| source.normalize(s)).collect();
         let (err_tx, err_rx) = mpsc::channel(64);
         let (hist_tx, hist_rx) = mpsc::channel(symbols.len());
         let fetcher = Self {
-            connector,
+            source,
             symbols,
             from,
+            to: None,
+            interval: "1d",
             debounce,
+            timeout: None,
             err_tx,
             hist_tx,
+            metrics: None,
+            tick: 0,
+            failures: Arc::new(Mutex::new(HashMap::new())),
+            in_flight: HashSet::new(),
+            breaker: None,
+            probes: 0,
+            n_workers: FETCH_WORKERS,
+            workers: vec![],
+            idle: vec![],
+            queue: VecDeque::new(),
+            ticks: HashMap::new(),
         };
         (fetcher, hist_rx, err_rx)
     }
+
+    /// Fetches from `source` instead of through `yahoo_finance_api`.
+    pub fn with_source(mut self, source: Arc<dyn QuoteSource>) -> Self {
+        self.symbols = self.symbols.iter().map(|s| source.normalize(s)).collect();
+        self.source = source;
+        self
+    }
+
+    /// Fetch quotes up to `to` instead of up to the time of each fetch.
+    pub fn with_to(mut self, to: Option<DateTime<Utc>>) -> Self {
+        self.to = to;
+        self
+    }
+
+    /// Fetches bars of `interval`, e.g. `15m` for `Timeframe::Minutes(15)`,
+    /// instead of daily ones.
+    pub fn with_interval(mut self, interval: &'static str) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Gives up on requests that have not been answered within `timeout`, and
+    /// reports them as `ErrorKind::Timeout`.
+    pub fn with_timeout(mut self, timeout: Option<time::Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reports fetch durations and outcomes to `metrics`.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Stops fetching while `breaker` is open, except for a single symbol
+    /// every probe interval.
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(Arc::new(Mutex::new(breaker)));
+        self
+    }
+
+    /// Fetches with `n` workers, i.e. at most `n` symbols at a time.
+    pub fn with_workers(mut self, n: usize) -> Self {
+        self.n_workers = n.max(1);
+        self
+    }
+
+    /// The sending end of the error stream, so that later stages can report
+    /// their errors alongside the fetch errors.
+    pub fn error_sender(&self) -> mpsc::Sender<Error> {
+        self.err_tx.clone()
+    }
+
+    pub fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    pub fn drop_symbols(&mut self, symbols: &[String]) {
+        self.symbols.retain(|s| !symbols.contains(s));
+    }
+
+    /// Fetches the last few days for each symbol, to find out which symbols
+    /// Yahoo does not know (anymore), before they produce errors every tick.
+    /// Fetches at most as many symbols at a time as there are workers.
+    pub async fn validate(&self) -> Validation {
+        let to = Utc::now();
+        let from = to - chrono::Duration::days(10);
+        let debounce = self.debounce;
+        let timeout = self.timeout;
+        let workers = Arc::new(Semaphore::new(self.n_workers));
+
+        let tasks: Vec<_> = self
+            .symbols
+            .iter()
+            .enumerate()
+            .map(|(i, symbol)| {
+                let mut fetch_spec = FetchSpec {
+                    from,
+                    to,
+                    interval: self.interval,
+                    source: self.source.clone(),
+                    symbol: symbol.to_string(),
+                    tick: 0,
+                    timeout,
+                };
+                let workers = workers.clone();
+                actix::spawn(async move {
+                    time::sleep(debounce * i as u32).await;
+                    // never closed
+                    let _worker = workers.acquire().await.unwrap();
+                    let result = fetch_spec.execute().await;
+                    (fetch_spec.symbol, result)
+                })
+            })
+            .collect();
+
+        let mut validation = Validation::default();
+        for task in tasks {
+            match task.await.unwrap() {
+                (_, Ok(_)) => {}
+                (symbol, Err(e)) if is_unknown_symbol(&e.kind) => validation.unknown.push(symbol),
+                (_, Err(e)) => validation.failed.push(e),
+            }
+        }
+        validation
+    }
+
+    /// Hands queued fetches to idle workers, one each.
+    fn dispatch(&mut self, ctx: &mut Context<Self>) {
+        while !self.queue.is_empty() {
+            let worker = match self.idle.pop() {
+                Some(worker) => worker,
+                None => return,
+            };
+            let fetch = self.queue.pop_front().unwrap();
+            let (tick, symbol) = (fetch.tick, fetch.symbol.clone());
+            ctx.spawn(self.workers[worker].send(fetch).into_actor(self).map(
+                move |_, trigger, ctx| {
+                    trigger.idle.push(worker);
+                    trigger.fetched(tick, &symbol);
+                    trigger.dispatch(ctx);
+                },
+            ));
+        }
+    }
+
+    /// Releases `symbol` for later ticks, and completes `tick` with its last
+    /// fetch.
+    fn fetched(&mut self, tick: u64, symbol: &str) {
+        self.in_flight.remove(symbol);
+        let remaining = match self.ticks.get_mut(&tick) {
+            Some((remaining, _, _)) => {
+                *remaining -= 1;
+                *remaining
+            }
+            None => return,
+        };
+        if remaining == 0 {
+            let (_, tick_start, _tick_span) = self.ticks.remove(&tick).unwrap();
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.observe_tick(tick_start.elapsed());
+            }
+        }
+    }
 }
 
-impl Actor for Fetcher {
+/// Outcome of `Fetcher::validate`.
+#[derive(Debug, Default)]
+pub struct Validation {
+    /// Symbols Yahoo does not know, e.g. because they were delisted.
+    pub unknown: Vec<String>,
+    /// Symbols that could not be checked, e.g. because of network errors.
+    pub failed: Vec<Error>,
+}
+
+/// Yahoo answers unknown symbols with 404 or without any quotes. Other
+/// failures, like 429 or 5xx, say nothing about the symbol.
+fn is_unknown_symbol(kind: &ErrorKind) -> bool {
+    match kind {
+        // `yahoo_finance_api` only keeps the status as text, `404 Not Found`
+        ErrorKind::Yahoo(YahooError::FetchFailed(status)) => status
+            .trim_start_matches("Status Code: ")
+            .starts_with("404"),
+        ErrorKind::Yahoo(YahooError::EmptyDataSet) => true,
+        ErrorKind::Http(HttpError::Status(status)) => *status == 404,
+        ErrorKind::Http(HttpError::Empty) => true,
+        _ => false,
+    }
+}
+
+impl Actor for FetchTrigger {
     type Context = Context<Self>;
+
+    fn started(&mut self, _: &mut Context<Self>) {
+        self.workers = (0..self.n_workers)
+            .map(|id| {
+                FetchSingle {
+                    id,
+                    source: self.source.clone(),
+                    interval: self.interval,
+                    timeout: self.timeout,
+                    err_tx: self.err_tx.clone(),
+                    hist_tx: self.hist_tx.clone(),
+                    metrics: self.metrics.clone(),
+                    failures: self.failures.clone(),
+                    breaker: self.breaker.clone(),
+                }
+                .start()
+            })
+            .collect();
+        // the lowest numbers are handed out first
+        self.idle = (0..self.n_workers).rev().collect();
+    }
 }
 
-impl Handler<StartFetch> for Fetcher {
+impl Handler<StartFetch> for FetchTrigger {
     type Result = ();
 
-    fn handle(&mut self, _: StartFetch, _cx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, _: StartFetch, ctx: &mut Context<Self>) -> Self::Result {
+        self.tick += 1;
+        let tick = self.tick;
+        let tick_span = info_span!("tick", tick, symbols = self.symbols.len());
         let from = self.from;
-        let now = Utc::now();
-        let debounce = self.debounce;
+        let to = self.to.unwrap_or_else(Utc::now);
+        let tick_start = time::Instant::now();
+
+        let admit = self.breaker.as_ref().map_or(Admit::All, |breaker| {
+            breaker.lock().unwrap().admit(tick_start)
+        });
+        let candidates = match admit {
+            Admit::All => &self.symbols[..],
+            Admit::Probe => {
+                // a symbol that is still in flight would be skipped below, and
+                // the breaker would wait for the outcome of a probe forever
+                let n = self.symbols.len();
+                let probe = (0..n)
+                    .map(|k| (self.probes + k) % n)
+                    .find(|&i| !self.in_flight.contains(&self.symbols[i]));
+                match probe {
+                    Some(i) => {
+                        self.probes = i + 1;
+                        debug!(parent: &tick_span, symbol = %self.symbols[i], "probing");
+                        &self.symbols[i..=i]
+                    }
+                    None => {
+                        if let Some(breaker) = self.breaker.as_ref() {
+                            breaker.lock().unwrap().cancel_probe();
+                        }
+                        debug!(parent: &tick_span, "all symbols in flight, probing later");
+                        return;
+                    }
+                }
+            }
+            _ => {
+                debug!(parent: &tick_span, "circuit open, skipping");
+                return;
+            }
+        };
+
+        // a fetch that is still running from a previous tick would race with
+        // a new one, so the symbol sits this tick out
+        let mut symbols = Vec::with_capacity(candidates.len());
+        let mut skipped = vec![];
+        for symbol in candidates.iter() {
+            if self.in_flight.insert(symbol.clone()) {
+                symbols.push(symbol.clone());
+            } else {
+                skipped.push(symbol.as_str());
+            }
+        }
+        if !skipped.is_empty() {
+            warn!(
+                parent: &tick_span,
+                symbols = %skipped.join(","),
+                "previous fetch still in flight, skipping"
+            );
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.fetch_skipped(skipped.len() as u64);
+            }
+        }
+        if symbols.is_empty() {
+            return;
+        }
 
-        for (i, symbol) in self.symbols.iter().enumerate() {
-            let mut fetch_spec = FetchSpec {
+        let probe = admit == Admit::Probe;
+        self.ticks
+            .insert(tick, (symbols.len(), tick_start, tick_span.clone()));
+        for (i, symbol) in symbols.into_iter().enumerate() {
+            let fetch = Fetch {
+                symbol,
                 from,
-                to: now,
-                connector: self.connector.clone(),
-                symbol: symbol.to_string(),
+                to,
+                tick,
+                probe,
+                span: tick_span.clone(),
             };
-
-            let err_tx = self.err_tx.clone();
-            let hist_tx = self.hist_tx.clone();
-            actix::spawn(async move {
-                // debounce, becouse otherwise we get a problem with the API
-                // when setting this to 5ms, it fails a lot
-                // (`connection to yahoo finance server failed`)
-                // set it to 15ms instead, and it mostly passes
-                // Problem seems to be related to DNS lookups
-                time::sleep(debounce * i as u32).await;
-
-                match fetch_spec.execute().await {
-                    Ok(h) => hist_tx.send(h).await.unwrap(),
-                    Err(e) => err_tx.send(e).await.unwrap(),
-                }
+            // debounce, becouse otherwise we get a problem with the API
+            // when setting this to 5ms, it fails a lot
+            // (`connection to yahoo finance server failed`)
+            // set it to 15ms instead, and it mostly passes
+            // Problem seems to be related to DNS lookups
+            ctx.run_later(self.debounce * i as u32, move |trigger, ctx| {
+                trigger.queue.push_back(fetch);
+                trigger.dispatch(ctx);
             });
         }
     }
 }
 
+impl Handler<InFlight> for FetchTrigger {
+    type Result = usize;
+
+    fn handle(&mut self, _: InFlight, _: &mut Context<Self>) -> usize {
+        self.in_flight.len()
+    }
+}
+
+/// Asks a `FetchSingle` to fetch `symbol` from `from` to `to`.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Fetch {
+    pub symbol: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub tick: u64,
+    /// Whether this is the breaker's probe, see `CircuitBreaker::record`.
+    pub probe: bool,
+    /// Parent of the span of the fetch, e.g. that of its tick.
+    pub span: Span,
+}
+
+/// Fetches one symbol at a time for a `FetchTrigger`, and sends the history or
+/// the error down the trigger's channels.
+pub struct FetchSingle {
+    id: usize,
+    source: Arc<dyn QuoteSource>,
+    interval: &'static str,
+    timeout: Option<time::Duration>,
+    err_tx: mpsc::Sender<Error>,
+    hist_tx: mpsc::Sender<StockHistory>,
+    metrics: Option<Metrics>,
+    // shared with the other workers, as a symbol may be fetched by any of them
+    failures: Arc<Mutex<HashMap<String, u32>>>,
+    breaker: Option<Arc<Mutex<CircuitBreaker>>>,
+}
+
+impl Actor for FetchSingle {
+    type Context = Context<Self>;
+}
+
+impl Handler<Fetch> for FetchSingle {
+    // holds back the next fetch until this one has completed
+    type Result = AtomicResponse<Self, ()>;
+
+    fn handle(&mut self, fetch: Fetch, _: &mut Context<Self>) -> Self::Result {
+        let tick = fetch.tick;
+        let probe = fetch.probe;
+        let fetch_span = debug_span!(
+            parent: &fetch.span,
+            "fetch",
+            tick,
+            symbol = %fetch.symbol,
+            worker = self.id
+        );
+        // the circuit may have opened since the tick admitted this fetch
+        if let Some(breaker) = self.breaker.as_ref() {
+            if !breaker.lock().unwrap().allows(probe) {
+                debug!(parent: &fetch_span, "circuit open, dropping fetch");
+                return AtomicResponse::new(Box::pin(actix::fut::ready(())));
+            }
+        }
+        let mut fetch_spec = FetchSpec {
+            from: fetch.from,
+            to: fetch.to,
+            interval: self.interval,
+            source: self.source.clone(),
+            symbol: fetch.symbol,
+            tick,
+            timeout: self.timeout,
+        };
+
+        let worker = self.id;
+        let err_tx = self.err_tx.clone();
+        let hist_tx = self.hist_tx.clone();
+        let metrics = self.metrics.clone();
+        let failures = self.failures.clone();
+        let breaker = self.breaker.clone();
+        let fetch = async move {
+            let start = time::Instant::now();
+            let result = fetch_spec.execute().await;
+            debug!(
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                ok = result.is_ok(),
+                "fetched"
+            );
+            if let Some(breaker) = breaker {
+                let failed = matches!(result, Err(ref e) if e.stage == Stage::Fetch);
+                breaker
+                    .lock()
+                    .unwrap()
+                    .record(failed, probe, time::Instant::now());
+            }
+            if let Some(metrics) = metrics {
+                metrics.observe_fetch(start.elapsed());
+                metrics.observe_worker_fetch(worker, start.elapsed());
+                match result {
+                    Ok(ref h) => metrics.fetch_succeeded(&h.symbol),
+                    Err(ref e) => metrics.fetch_failed(e.kind.name()),
+                }
+            }
+
+            match result {
+                Ok(h) => {
+                    failures.lock().unwrap().remove(&h.symbol);
+                    hist_tx.send(h).await.unwrap()
+                }
+                Err(e) => {
+                    let attempt = {
+                        let mut failures = failures.lock().unwrap();
+                        let attempt = failures.entry(fetch_spec.symbol.clone()).or_insert(0);
+                        *attempt += 1;
+                        *attempt
+                    };
+                    err_tx
+                        .send(e.with_attempt(attempt).with_tick(tick))
+                        .await
+                        .unwrap()
+                }
+            }
+        };
+        AtomicResponse::new(Box::pin(fetch.instrument(fetch_span).into_actor(self)))
+    }
+}
+
 struct FetchSpec {
-    connector: Arc<YahooConnector>,
+    source: Arc<dyn QuoteSource>,
     symbol: String,
     from: DateTime<Utc>,
     to: DateTime<Utc>,
+    interval: &'static str,
+    tick: u64,
+    timeout: Option<time::Duration>,
 }
 
 impl FetchSpec {
-    pub async fn execute(&mut self) -> Result<StockHistory, YahooError> {
-        let response = self
-            .connector
-            .get_quote_history_interval(&self.symbol, self.from, self.to, "1d")
-            .await;
-
-        match response {
-            Ok(res) => match res.quotes() {
-                Ok(quotes) => Ok(StockHistory {
-                    symbol: self.symbol.clone(),
-                    quotes,
-                    from: self.from,
-                }),
-                Err(e) => Err(e),
-            },
-            Err(e) => Err(e),
+    pub async fn execute(&mut self) -> Result<StockHistory, Error> {
+        let timeout = match self.timeout {
+            None => return self.fetch().await,
+            Some(timeout) => timeout,
+        };
+        match time::timeout(timeout, self.fetch()).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::new(
+                Some(self.symbol.clone()),
+                Stage::Fetch,
+                ErrorKind::Timeout(timeout),
+            )),
         }
     }
+
+    async fn fetch(&mut self) -> Result<StockHistory, Error> {
+        let chart = self
+            .source
+            .history(&self.symbol, self.from, self.to, self.interval)
+            .await?;
+        Ok(StockHistory {
+            symbol: self.symbol.clone(),
+            quotes: chart.quotes,
+            missing: chart.missing,
+            instrument: chart.instrument,
+            splits: chart.splits,
+            dividends: chart.dividends,
+            from: self.from,
+            tick: self.tick,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+
+    // answers after a while, and records how many requests overlapped
+    #[derive(Default)]
+    struct SlowSource {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl QuoteSource for SlowSource {
+        async fn history(
+            &self,
+            _: &str,
+            _: DateTime<Utc>,
+            _: DateTime<Utc>,
+            _: &str,
+        ) -> Result<Chart, Error> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            time::sleep(time::Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(Chart {
+                quotes: vec![],
+                missing: vec![],
+                instrument: None,
+                splits: vec![],
+                dividends: vec![],
+            })
+        }
+    }
+
+    #[actix_rt::test]
+    async fn workers() {
+        let source = Arc::new(SlowSource::default());
+        let symbols: Vec<String> = (0..10).map(|i| format!("S{}", i)).collect();
+        let (fetcher, mut hist_rx, _) =
+            Fetcher::new(symbols.clone(), Utc::now(), time::Duration::from_millis(0));
+        let fetcher = fetcher.with_source(source.clone()).with_workers(3).start();
+
+        fetcher.send(StartFetch).await.unwrap();
+        let mut fetched: Vec<String> = vec![];
+        for _ in 0..10 {
+            fetched.push(hist_rx.recv().await.unwrap().symbol);
+        }
+        fetched.sort();
+        assert_eq!(fetched, symbols);
+        assert_eq!(source.max_running.load(Ordering::SeqCst), 3);
+    }
+
+    #[actix_rt::test]
+    async fn validate_workers() {
+        let source = Arc::new(SlowSource::default());
+        let symbols: Vec<String> = (0..10).map(|i| format!("S{}", i)).collect();
+        let (fetcher, _, _) = Fetcher::new(symbols, Utc::now(), time::Duration::from_millis(0));
+        let fetcher = fetcher.with_source(source.clone()).with_workers(3);
+
+        let validation = fetcher.validate().await;
+        assert!(validation.unknown.is_empty() && validation.failed.is_empty());
+        assert_eq!(source.max_running.load(Ordering::SeqCst), 3);
+    }
+
+    // hangs on `HANG`, fails everything else right away
+    #[derive(Default)]
+    struct HangingSource {
+        fetches: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl QuoteSource for HangingSource {
+        async fn history(
+            &self,
+            symbol: &str,
+            _: DateTime<Utc>,
+            _: DateTime<Utc>,
+            _: &str,
+        ) -> Result<Chart, Error> {
+            self.fetches.lock().unwrap().push(symbol.to_string());
+            if symbol == "HANG" {
+                time::sleep(time::Duration::from_secs(600)).await;
+            }
+            Err(Error::new(
+                Some(symbol.to_string()),
+                Stage::Fetch,
+                ErrorKind::Http(HttpError::Status(503)),
+            ))
+        }
+    }
+
+    #[actix_rt::test]
+    async fn probe_in_flight() {
+        let source = Arc::new(HangingSource::default());
+        let (breaker, _) = CircuitBreaker::new(BreakerConfig {
+            failure_ratio: 0.5,
+            min_requests: 1,
+            window: 1,
+            probe_interval: 0,
+        });
+        let symbols = vec!["HANG".to_string(), "FAIL".to_string()];
+        let (fetcher, _hist_rx, mut err_rx) =
+            Fetcher::new(symbols, Utc::now(), time::Duration::from_millis(0));
+        let fetcher = fetcher
+            .with_source(source.clone())
+            .with_breaker(breaker)
+            .start();
+
+        // `FAIL` opens the circuit while `HANG` is still in flight, so the
+        // probes skip it
+        for _ in 0..3 {
+            fetcher.send(StartFetch).await.unwrap();
+            let e = time::timeout(time::Duration::from_secs(1), err_rx.recv())
+                .await
+                .expect("no probe was sent")
+                .unwrap();
+            assert_eq!(e.symbol.as_deref(), Some("FAIL"));
+            // the error is sent before the fetch counts as completed
+            time::sleep(time::Duration::from_millis(50)).await;
+        }
+        assert_eq!(
+            *source.fetches.lock().unwrap(),
+            vec!["HANG", "FAIL", "FAIL", "FAIL"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn hanging_worker() {
+        let source = Arc::new(HangingSource::default());
+        let symbols: Vec<String> = ["HANG", "A", "B", "C", "D"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (fetcher, _hist_rx, mut err_rx) =
+            Fetcher::new(symbols, Utc::now(), time::Duration::from_millis(0));
+        let fetcher = fetcher.with_source(source).with_workers(2).start();
+
+        // without a timeout, `HANG` keeps its worker, the other one does the rest
+        fetcher.send(StartFetch).await.unwrap();
+        let mut failed = vec![];
+        for _ in 0..4 {
+            let e = time::timeout(time::Duration::from_secs(1), err_rx.recv())
+                .await
+                .expect("a symbol is stuck behind HANG")
+                .unwrap();
+            failed.push(e.symbol.unwrap());
+        }
+        failed.sort();
+        assert_eq!(failed, vec!["A", "B", "C", "D"]);
+    }
+
+    #[actix_rt::test]
+    async fn open_while_debounced() {
+        let source = Arc::new(HangingSource::default());
+        let (breaker, _) = CircuitBreaker::new(BreakerConfig {
+            failure_ratio: 0.5,
+            min_requests: 1,
+            window: 1,
+            probe_interval: 600,
+        });
+        let symbols = vec!["A".to_string(), "B".to_string(), "C".to_string()];
+        let (fetcher, _hist_rx, mut err_rx) =
+            Fetcher::new(symbols, Utc::now(), time::Duration::from_millis(50));
+        let fetcher = fetcher
+            .with_source(source.clone())
+            .with_breaker(breaker)
+            .start();
+
+        // `A` opens the circuit before the others are due
+        fetcher.send(StartFetch).await.unwrap();
+        time::sleep(time::Duration::from_millis(300)).await;
+        assert_eq!(*source.fetches.lock().unwrap(), vec!["A"]);
+        assert_eq!(err_rx.recv().await.unwrap().symbol.as_deref(), Some("A"));
+        assert!(err_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn unknown_symbols() {
+        let yahoo = |status: &str| ErrorKind::Yahoo(YahooError::FetchFailed(status.to_string()));
+        assert!(is_unknown_symbol(&yahoo("Status Code: 404 Not Found")));
+        assert!(!is_unknown_symbol(&yahoo(
+            "Status Code: 429 Too Many Requests"
+        )));
+        assert!(!is_unknown_symbol(&yahoo(
+            "Status Code: 503 Service Unavailable"
+        )));
+        assert!(is_unknown_symbol(&ErrorKind::Http(HttpError::Status(404))));
+        assert!(is_unknown_symbol(&ErrorKind::Http(HttpError::Empty)));
+        assert!(!is_unknown_symbol(&ErrorKind::Http(HttpError::Status(500))));
+    }
 }